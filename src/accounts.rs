@@ -0,0 +1,197 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::constants;
+use crate::resources::AppConfig;
+use crate::signing;
+
+/// Which `Authenticator` the server logs players in with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AuthBackend {
+    Keys,      // Challenge signed with a key from `player_keys`
+    Passwords, // Accounts players make themselves, kept in `accounts_file`
+    Dev,       // Anyone is whoever they say they are, never on a real server
+}
+
+/// Checks login proofs. A proof is the player's name, the challenge the
+/// server gave their connection, and a secret. What the secret is depends
+/// on the backend.
+pub trait Authenticator: Send + Sync {
+    fn login(&self, name: &str, challenge: &str, secret: &str) -> Result<(), String>;
+
+    /// New account, only for backends that let players sign up. Logs the
+    /// player in too, asking again for an account they already own is fine.
+    fn register(&mut self, _name: &str, _secret: &str) -> Result<(), String> {
+        Err("This server doesn't take new accounts".to_string())
+    }
+
+    /// Does the proof have to carry a challenge we handed out
+    fn needs_challenge(&self) -> bool {
+        true
+    }
+}
+
+/// Whichever authenticator the config picked
+pub struct Accounts(pub Box<dyn Authenticator>);
+
+impl Default for Accounts {
+    fn default() -> Self {
+        // Nobody gets in until we're told who can
+        Accounts(Box::new(Keys { keys: HashMap::<String, String>::new() }))
+    }
+}
+
+impl Accounts {
+    pub fn open(conf: &AppConfig) -> Result<Self, String> {
+        Ok(Accounts(match conf.auth {
+            AuthBackend::Keys => Box::new(Keys { keys: conf.player_keys.clone() }),
+            AuthBackend::Passwords => Box::new(Passwords::open(&conf.accounts_file)?),
+            AuthBackend::Dev => {
+                warn!("Dev mode login, anyone can log in as anyone");
+                Box::new(Dev)
+            },
+        }))
+    }
+}
+
+/// Players sign the challenge, we have their public keys
+pub struct Keys {
+    keys: HashMap<String, String>, // Name -> hex public key
+}
+
+impl Authenticator for Keys {
+    fn login(&self, name: &str, challenge: &str, secret: &str) -> Result<(), String> {
+        let public = self.keys.get(name).ok_or_else(|| format!("{} isn't registered here", name))?;
+        let signature = hex::decode(secret).map_err(|_| "Signature isn't hex".to_string())?;
+        if !signing::verify(public, format!("{} {}", name, challenge).as_bytes(), &signature) {
+            return Err(format!("Signature doesn't match the key registered for {}", name));
+        }
+        Ok(())
+    }
+}
+
+/// Name and password, the password kept as a salted argon2 hash. The file
+/// is a line per account, the name and then the hash.
+pub struct Passwords {
+    path: String,
+    hashes: HashMap<String, String>, // Name -> encoded hash, salt and settings included
+}
+
+impl Passwords {
+    /// Accounts in `path`, none if it isn't there yet
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut hashes = HashMap::<String, String>::new();
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                    let mut words = line.split_whitespace();
+                    match (words.next(), words.next(), words.next()) {
+                        (Some(name), Some(hash), None) => hashes.insert(name.to_string(), hash.to_string()),
+                        _ => return Err(format!("{}:{} should be a name and a hash", path, n + 1)),
+                    };
+                }
+            },
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("Could not read {}: {}", path, e)),
+        }
+        Ok(Self { path: path.to_string(), hashes })
+    }
+}
+
+impl Authenticator for Passwords {
+    fn login(&self, name: &str, _challenge: &str, secret: &str) -> Result<(), String> {
+        // Same answer either way, no fishing for names
+        let wrong = || "Wrong name or password".to_string();
+        let hash = self.hashes.get(name).ok_or_else(wrong)?;
+        match argon2::verify_encoded(hash, secret.as_bytes()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(wrong()),
+            Err(e) => Err(format!("Stored password for {} is broken: {}", name, e)),
+        }
+    }
+
+    fn register(&mut self, name: &str, secret: &str) -> Result<(), String> {
+        if name.is_empty() || name.chars().count() > constants::MAX_NAME_LEN {
+            return Err(format!("Names have to be 1 to {} characters", constants::MAX_NAME_LEN));
+        }
+        // Anything else could break the file up into lines and words we didn't write
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err("Names can only have letters, numbers, _ and -".to_string());
+        }
+        // Clients keep asking on every reconnect, let the owner through
+        if self.hashes.contains_key(name) {
+            return self.login(name, "", secret).map_err(|_| format!("{} is taken", name));
+        }
+        if secret.chars().count() < constants::MIN_PASSWORD_LEN {
+            return Err(format!("Passwords have to be at least {} characters", constants::MIN_PASSWORD_LEN));
+        }
+
+        let salt = rand::random::<[u8; 16]>();
+        let hash = argon2::hash_encoded(secret.as_bytes(), &salt, &argon2::Config::default())
+            .map_err(|e| format!("Could not hash the password: {}", e))?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| writeln!(f, "{} {}", name, hash))
+            .map_err(|e| format!("Could not save the account: {}", e))?;
+        self.hashes.insert(name.to_string(), hash);
+        info!("New account for {}", name);
+        Ok(())
+    }
+}
+
+/// The old behaviour, for testing and replays
+pub struct Dev;
+
+impl Authenticator for Dev {
+    fn login(&self, _name: &str, _challenge: &str, _secret: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn needs_challenge(&self) -> bool {
+        false
+    }
+}
+
+/// What a client logs in with, its password if it has one, otherwise the
+/// challenge signed with its key
+pub fn proof(conf: &AppConfig, name: &str, challenge: &str) -> Result<String, String> {
+    match conf.password.as_str() {
+        "" => {
+            let key = signing::load_or_create(&conf.player_key)?;
+            signing::proof(name, challenge, &key)
+        },
+        password => Ok(format!("{} {} {}", name, challenge, password)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file nobody else is using, gone at the start of the test
+    fn scratch(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("realm-one-{}-{}.txt", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn names_cant_break_the_file() {
+        let path = scratch("names");
+        let mut accounts = Passwords::open(&path).unwrap();
+        for name in vec!["Tur nip", "Turnip\nMallory", "Turnip\t", "Tûrnip"] {
+            assert!(accounts.register(name, "long enough password").is_err(), "{:?} got in", name);
+        }
+        accounts.register("Turnip_2-b", "long enough password").unwrap();
+
+        let reopened = Passwords::open(&path).unwrap();
+        assert_eq!(reopened.hashes.len(), 1);
+        assert!(reopened.login("Turnip_2-b", "", "long enough password").is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+}
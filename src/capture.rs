@@ -0,0 +1,88 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::network::{Cmd, Pack};
+
+/// What happened on the wire, packs are stored after encryption comes off
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Entry {
+    Connect,
+    Disconnect,
+    In(Pack),
+    Out(Pack),
+    Handshake, // Encryption went on, what follows can't be replayed as it was
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub at_ms: u64, // Since the capture started
+    pub peer: SocketAddr,
+    pub entry: Entry,
+}
+
+/// Writes everything a `TcpSystem` sends and receives to a file, set
+/// `capture` in the config to turn it on. Play it back with `replay`.
+pub struct Recorder {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, peer: SocketAddr, entry: Entry) {
+        let record = Record {
+            at_ms: self.start.elapsed().as_millis() as u64,
+            peer,
+            entry,
+        };
+        // Flush as we go, the interesting captures end in a crash
+        let written = bincode::serialize_into(&mut self.file, &record)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|_| self.file.flush().map_err(|e| format!("{:?}", e)));
+        if let Err(e) = written {
+            warn!("Could not write to the capture: {}", e);
+        }
+    }
+}
+
+/// Keeps a password out of the capture, the proof's name and challenge stay
+pub fn redact(pack: Pack) -> Pack {
+    match pack.cmd {
+        Cmd::Connect(mut hello) => {
+            let kept = hello.proof.splitn(3, ' ').take(2).collect::<Vec<&str>>().join(" ");
+            hello.proof = format!("{} redacted", kept);
+            Pack::new(Cmd::Connect(hello), pack.dest)
+        },
+        _ => pack,
+    }
+}
+
+/// Read a whole capture back, a record cut short at the end is dropped
+pub fn load(path: &str) -> std::io::Result<Vec<Record>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut records = Vec::<Record>::new();
+
+    loop {
+        match bincode::deserialize_from(&mut file) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                if let bincode::ErrorKind::Io(_) = *e {
+                    break; // End of the file
+                }
+                warn!("Capture {} has a bad record after {} good ones: {:?}", path, records.len(), e);
+                break;
+            },
+        }
+    }
+    Ok(records)
+}
@@ -72,6 +72,7 @@ pub struct LifeformComponent {
     pub orientation: Orientation,
     pub hp: f32,
     pub kind: LifeformType,
+    pub rev: u64, // Bumped by the server every time this lifeform changes
 }
 
 impl LifeformComponent {
@@ -88,6 +89,7 @@ impl LifeformComponent {
             orientation: Orientation::North,
             hp: 100.0,
             kind: LifeformType::Player,
+            rev: 0,
         }
     }
 
@@ -105,6 +107,7 @@ impl LifeformComponent {
             orientation: Orientation::South,
             hp: monster.hp,
             kind: LifeformType::Monster,
+            rev: 0,
         }
     }
 
@@ -221,6 +224,11 @@ impl LifeformComponent {
         false
     }
     
+    /// Is lifeform within your vision plus a margin
+    pub fn in_view(&self, lifeform: &LifeformComponent, margin: f32) -> bool {
+        distance(&self.xy(), &lifeform.xy()) < self.vision + margin
+    }
+    
     /// Is lifeform in front of you
     pub fn is_in_front(&self, lifeform: &LifeformComponent) -> bool {
         &self.in_front() == &lifeform.trans()
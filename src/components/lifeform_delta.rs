@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+use crate::components::{LifeformComponent, Orientation, Outfit};
+
+/// Only the parts of a lifeform that changed. `rev` has to follow on
+/// directly from the copy it is applied to, otherwise that copy is stale.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LifeformDelta {
+    pub uid: u64,
+    pub rev: u64,
+    pub pos: Option<(f32, f32)>,
+    pub orientation: Option<Orientation>,
+    pub hp: Option<f32>,
+    pub skin: Option<Outfit>,
+}
+
+impl LifeformDelta {
+    /// What it takes to get from old to new, None if nothing we track changed
+    pub fn diff(old: &LifeformComponent, new: &LifeformComponent) -> Option<Self> {
+        let delta = Self {
+            uid: new.id(),
+            rev: new.rev,
+            pos: if (old.x, old.y) != (new.x, new.y) { Some((new.x, new.y)) } else { None },
+            orientation: if old.orientation != new.orientation { Some(new.orientation.clone()) } else { None },
+            hp: if old.hp != new.hp { Some(new.hp) } else { None },
+            skin: if old.skin != new.skin { Some(new.skin.clone()) } else { None },
+        };
+
+        match delta.is_empty() {
+            true => None,
+            false => Some(delta),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos.is_none() && self.orientation.is_none() && self.hp.is_none() && self.skin.is_none()
+    }
+
+    /// Can this be applied on top of our copy
+    pub fn follows(&self, lifeform: &LifeformComponent) -> bool {
+        self.rev == lifeform.rev + 1
+    }
+
+    pub fn apply(&self, lifeform: &mut LifeformComponent) {
+        if let Some((x, y)) = self.pos {
+            lifeform.x = x;
+            lifeform.y = y;
+        }
+        if let Some(orientation) = &self.orientation {
+            lifeform.orientation = orientation.clone();
+        }
+        if let Some(hp) = self.hp {
+            lifeform.hp = hp;
+        }
+        if let Some(skin) = &self.skin {
+            lifeform.skin = skin.clone();
+        }
+        lifeform.rev = self.rev;
+    }
+}
@@ -4,6 +4,9 @@ pub use self::lifeform::LifeformType;
 pub use self::lifeform::Orientation;
 pub use self::lifeform::get_rand_orientation;
 
+mod lifeform_delta;
+pub use self::lifeform_delta::LifeformDelta;
+
 mod monster; 
 pub use self::monster::Monster;
 
@@ -18,6 +21,7 @@ pub use self::outfits::Skins;
 pub use self::outfits::Outfit;
 pub use self::outfits::get_outfit;
 pub use self::outfits::outfit_from_str;
+pub use self::outfits::skin_from_str;
 
 mod walk_animation;
 pub use self::walk_animation::WalkAnimation;
@@ -31,3 +35,9 @@ pub use self::movement::Move;
 mod item;
 pub use self::item::Item;
 
+mod player_one;
+pub use self::player_one::PlayerOne;
+
+mod waypoints;
+pub use self::waypoints::Waypoints;
+
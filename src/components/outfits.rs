@@ -14,6 +14,20 @@ pub fn outfit_from_str(skin: String) -> Outfit {
     }
 }
 
+pub fn skin_from_str(skin: &str) -> Option<Skins> {
+    match skin {
+        "Nude"     => Some(Skins::Nude),
+        "Male"     => Some(Skins::Male),
+        "Female"   => Some(Skins::Female),
+        "Skeleton" => Some(Skins::Skeleton),
+        "Slime"    => Some(Skins::Slime),
+        "Bat"      => Some(Skins::Bat),
+        "Ghost"    => Some(Skins::Ghost),
+        "Spider"   => Some(Skins::Spider),
+        _          => None,
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Skins {
     Nude,
@@ -0,0 +1,10 @@
+use amethyst::ecs::{Component, NullStorage};
+
+/// Marks the lifeform this client is driving. Its position belongs to the
+/// PlayerSystem, everyone else gets theirs straight from the server.
+#[derive(Default, Debug)]
+pub struct PlayerOne;
+
+impl Component for PlayerOne {
+    type Storage = NullStorage<Self>;
+}
@@ -0,0 +1,57 @@
+use amethyst::{
+    ecs::{Component, DenseVecStorage, FlaggedStorage},
+};
+
+use nalgebra::base::{Vector3};
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Steps a remote lifeform still has to walk, oldest first. Each step waits
+/// a little before it gets played so late packets don't make them stutter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waypoints {
+    steps: VecDeque<(Vector3<f32>, Instant)>,
+}
+
+impl Default for Waypoints {
+    fn default() -> Self {
+        Waypoints::new()
+    }
+}
+
+impl Waypoints {
+    pub fn new() -> Self {
+        Self {
+            steps: VecDeque::<(Vector3<f32>, Instant)>::new(),
+        }
+    }
+
+    pub fn push(&mut self, step: Vector3<f32>) {
+        self.steps.push_back((step, Instant::now()));
+    }
+
+    /// Next step that has waited at least `delay_ms`
+    pub fn next(&mut self, delay_ms: u128) -> Option<Vector3<f32>> {
+        let ready = match self.steps.front() {
+            Some((_, arrived)) => arrived.elapsed().as_millis() >= delay_ms,
+            None => false,
+        };
+
+        match ready {
+            true => self.steps.pop_front().map(|(step, _)| step),
+            false => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+}
+
+impl Component for Waypoints {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
@@ -3,6 +3,35 @@ pub const PLAYER_MOVE: f32 = 16.0;
 pub const TILE_PER_PLAYER: f32 = PLAYER_MOVE / TILE_SIZE;
 pub const ACTION_DELAY_MS: u128 = 500;
 pub const TYPING_DELAY_MS: u128 = 150;
+pub const INTERP_DELAY_MS: u128 = 100;
+pub const VISION_MARGIN: f32 = PLAYER_MOVE;
+pub const MAX_MALFORMED_PACKS: u32 = 5;
+pub const KICK_COOLDOWN_MS: u128 = 30000; // Laminar has no stream to close, so we ignore them instead
+pub const PING_INTERVAL_MS: u128 = 1000;
+pub const MOVE_COOLDOWN_MS: u64 = ACTION_DELAY_MS as u64;
+pub const MELEE_COOLDOWN_MS: u64 = ACTION_DELAY_MS as u64;
+pub const OUTFIT_COOLDOWN_MS: u64 = 2000;
+pub const ACTION_BURST: u32 = 2;
+pub const RATE_VIOLATION_WINDOW_MS: u128 = 10000;
+pub const MAX_RATE_VIOLATIONS: u32 = 10;
+pub const MAX_BATCH_BYTES: usize = 1024;
+pub const MAX_PENDING_BYTES: usize = 65536;
+pub const FILE_CHUNK_BYTES: usize = 768;
+pub const MAX_FILE_SENDS: u32 = 3; // Per file per connection, in case a copy fails its hash check
+pub const TILESET_PATH: &str = "resources/sprites/master16.tsx";
+pub const MAP_CACHE_DIR: &str = "resources/cache";
+pub const TICKET_TTL_SECS: u64 = 30;
+pub const SPECTATOR_PAN_SPEED: f32 = 200.0; // Pixels a second
+pub const MAX_ADMIN_LINE: usize = 1024;
+pub const CHALLENGE_TTL_MS: u128 = 10000;
+pub const CHALLENGE_WAIT_MS: u128 = 5000; // Client gives up on a server that never sends one
+pub const MAX_NAME_LEN: usize = 24;
+pub const MIN_PASSWORD_LEN: usize = 8;
+pub const MAX_LOGIN_ATTEMPTS: u32 = 3; // Per connection
+pub const MAX_LOGINS_PER_FRAME: usize = 2; // Each can be a password hash, they're slow on purpose
+pub const MAX_QUEUED_LOGINS: usize = 64;
+pub const RESYNC_RETRY_MS: u128 = 2000;
+pub const REPLAY_WINDOW_MS: u64 = 100; // How early or late a replayed pack can go out and still match
 
 // pub const SERVER_IP : &str = "127.0.0.1:3456";
 // pub const CLIENT_IP: &str = "127.0.0.1:3455";
@@ -0,0 +1,222 @@
+use log::{info, warn, error};
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::accounts;
+use crate::components::Action;
+use crate::constants;
+use crate::network::{Cmd, ConnectReply, Dest, Hello, Pack, Reassembly, PROTOCOL_VERSION};
+use crate::resources::{AppConfig, ServerStats};
+use crate::signing;
+
+/// Everything the simulated clients have seen, shared between their threads
+#[derive(Default)]
+struct Report {
+    connected: u32,
+    accepted: u32,
+    failed: u32,
+    packs_in: u64,
+    packs_out: u64,
+    acks: Vec<Duration>,    // Input sent until the server acked it
+    updates: Vec<Duration>, // Move sent until our own update came back
+    server: Option<ServerStats>,
+}
+
+/// Open `clients` connections to the server in the config, walk and fight
+/// with all of them for `seconds`, printing how it went every few seconds.
+/// Plain TCP only, the load test doesn't do encryption or laminar.
+pub fn run(config: &AppConfig, args: &[String]) -> Result<(), String> {
+    let clients: u32 = args.get(0).map_or(Ok(10), |a| a.parse()).map_err(|e| format!("Bad client count: {}", e))?;
+    let seconds: u64 = args.get(1).map_or(Ok(60), |a| a.parse()).map_err(|e| format!("Bad duration: {}", e))?;
+    if config.encrypt {
+        return Err("The load test doesn't encrypt, turn encrypt off on the server".to_string());
+    }
+    if !config.password.is_empty() {
+        return Err("The load test doesn't encrypt, so it won't send a password. Use player_key".to_string());
+    }
+
+    // Everyone logs in with our key, the server has to know it for each name
+    let key = signing::load_or_create(&config.player_key)?;
+    info!("Clients sign in with the public key {}", signing::public_key(&key)?);
+
+    info!("Load testing {} with {} clients for {}s", config.server_ip, clients, seconds);
+    let report = Arc::new(Mutex::new(Report::default()));
+    let running = Arc::new(AtomicBool::new(true));
+    let mut threads = Vec::new();
+
+    for n in 0..clients {
+        let (report, running) = (report.clone(), running.clone());
+        let server = config.server_ip.clone();
+        let name = format!("{}{}", config.player_name, n);
+        let config = config.clone();
+        threads.push(thread::spawn(move || {
+            if let Err(e) = client(&server, &name, &config, n == 0, &report, &running) {
+                warn!("{} gave up: {}", name, e);
+                report.lock().unwrap().failed += 1;
+            }
+        }));
+        thread::sleep(Duration::from_millis(20)); // Don't hit the server with everyone at once
+    }
+
+    let start = Instant::now();
+    let mut last = (Instant::now(), 0u64, 0u64, ServerStats::default());
+    while start.elapsed().as_secs() < seconds {
+        thread::sleep(Duration::from_secs(5));
+        let mut report = report.lock().unwrap();
+        print(&mut report, &mut last);
+    }
+
+    running.store(false, Ordering::Relaxed);
+    for t in threads {
+        let _ = t.join();
+    }
+    info!("Load test done");
+    Ok(())
+}
+
+/// Rates since the last time this was called, and the round trip spread
+fn print(report: &mut Report, last: &mut (Instant, u64, u64, ServerStats)) {
+    let secs = last.0.elapsed().as_secs_f64();
+    info!(
+        "{} connected, {} accepted, {} failed. In {:.0} packs/s, out {:.0} packs/s",
+        report.connected, report.accepted, report.failed,
+        (report.packs_in - last.1) as f64 / secs,
+        (report.packs_out - last.2) as f64 / secs,
+    );
+    info!("Ack round trip {}", spread(&mut report.acks));
+    info!("Update round trip {}", spread(&mut report.updates));
+
+    if let Some(server) = report.server.clone() {
+        let ticks = server.ticks - last.3.ticks;
+        let avg = match ticks {
+            0 => 0,
+            n => (server.tick_total_us - last.3.tick_total_us) / n,
+        };
+        info!(
+            "Server: {} clients, {} lifeforms, {:.0} ticks/s at {}us avg ({}us worst ever), {:.0} packs/s in, {:.0} packs/s out",
+            server.clients, server.lifeforms, ticks as f64 / secs, avg, server.tick_max_us,
+            (server.packs_in - last.3.packs_in) as f64 / secs,
+            (server.packs_out - last.3.packs_out) as f64 / secs,
+        );
+        last.3 = server;
+    }
+    *last = (Instant::now(), report.packs_in, report.packs_out, last.3.clone());
+    report.acks.clear();
+    report.updates.clear();
+}
+
+fn spread(times: &mut Vec<Duration>) -> String {
+    if times.is_empty() {
+        return "n/a".to_string();
+    }
+    times.sort();
+    let at = |p: usize| times[(times.len() - 1) * p / 100].as_millis();
+    format!("p50 {}ms, p95 {}ms, max {}ms over {}", at(50), at(95), at(100), times.len())
+}
+
+/// One simulated player, runs until the test is over or the server hangs up
+fn client(server: &str, name: &str, config: &AppConfig, asks_stats: bool, report: &Mutex<Report>, running: &AtomicBool) -> Result<(), String> {
+    let mut stream = TcpStream::connect(server).map_err(|e| format!("{}", e))?;
+    stream.set_nodelay(true).map_err(|e| format!("{}", e))?;
+    stream.set_read_timeout(Some(Duration::from_millis(10))).map_err(|e| format!("{}", e))?;
+    report.lock().unwrap().connected += 1;
+
+    send(&mut stream, Cmd::Greet(PROTOCOL_VERSION), report)?;
+
+    let mut rng = rand::thread_rng();
+    let peer = stream.peer_addr().map_err(|e| format!("{}", e))?;
+    let mut partial = Reassembly::default();
+    let mut me: Option<u64> = None;
+    let mut seq = 0u32;
+    let mut sent = HashMap::<u32, Instant>::new();
+    let mut moved: Option<Instant> = None; // Waiting to hear about our own move
+    let mut timer = Instant::now();
+    let mut stats_timer = Instant::now();
+
+    while running.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if me.is_some() && now.duration_since(timer).as_millis() >= constants::ACTION_DELAY_MS {
+            timer = now;
+            seq += 1;
+            let act = match rng.gen_range(0, 10) {
+                0 => Action::Melee,
+                1 => Action::Rotate(rng.gen()),
+                _ => Action::Move(rng.gen()),
+            };
+            if let Action::Move(_) = act {
+                moved.get_or_insert(now);
+            }
+            sent.insert(seq, now);
+            send(&mut stream, Cmd::Input(seq, act), report)?;
+        }
+        if asks_stats && me.is_some() && now.duration_since(stats_timer).as_secs() >= 1 {
+            stats_timer = now;
+            send(&mut stream, Cmd::Stats, report)?;
+        }
+
+        for pack in receive(&mut stream, peer, &mut partial)? {
+            report.lock().unwrap().packs_in += 1;
+            match pack.cmd {
+                Cmd::Ping(n) => send(&mut stream, Cmd::Pong(n), report)?,
+                Cmd::Challenge(challenge) => {
+                    let mut hello = Hello::new(accounts::proof(config, name, &challenge)?);
+                    hello.register = config.register;
+                    send(&mut stream, Cmd::Connect(hello), report)?;
+                },
+                Cmd::ConnectReply(ConnectReply::Accept(_, _)) => report.lock().unwrap().accepted += 1,
+                Cmd::ConnectReply(ConnectReply::Reject(reason)) => return Err(format!("Rejected: {}", reason)),
+                Cmd::Kicked(reason) => return Err(format!("Kicked: {}", reason)),
+                Cmd::InsertPlayer1(lf) => me = Some(lf.id()),
+                Cmd::Ack(ack) => {
+                    if let Some(at) = sent.remove(&ack.seq) {
+                        report.lock().unwrap().acks.push(at.elapsed());
+                    }
+                },
+                Cmd::UpdatePlayer(ref lf) if Some(lf.id()) == me => updated(&mut moved, report),
+                Cmd::UpdateLifeform(ref delta) if Some(delta.uid) == me && delta.pos.is_some() => updated(&mut moved, report),
+                Cmd::StatsReply(stats) => report.lock().unwrap().server = Some(stats),
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn updated(moved: &mut Option<Instant>, report: &Mutex<Report>) {
+    if let Some(at) = moved.take() {
+        report.lock().unwrap().updates.push(at.elapsed());
+    }
+}
+
+fn send(stream: &mut TcpStream, cmd: Cmd, report: &Mutex<Report>) -> Result<(), String> {
+    let bin = Pack::new(cmd, Dest::All).to_bin().map_err(|e| format!("{:?}", e))?;
+    stream.write_all(&bin).map_err(|e| format!("{}", e))?;
+    report.lock().unwrap().packs_out += 1;
+    Ok(())
+}
+
+/// Whatever whole packs have arrived, anything cut short waits in `partial` for next time
+fn receive(stream: &mut TcpStream, server: SocketAddr, partial: &mut Reassembly) -> Result<Vec<Pack>, String> {
+    let mut chunk = [0u8; 65536];
+    let read = match stream.read(&mut chunk) {
+        Ok(0) => return Err("Server hung up".to_string()),
+        Ok(n) => n,
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => 0,
+        Err(e) => return Err(format!("{}", e)),
+    };
+
+    let mut packs = Vec::<Pack>::new();
+    for pack in partial.feed(server, &chunk[..read]) {
+        match pack {
+            Ok(pack) => packs.push(pack),
+            Err(e) => error!("Malformed pack from the server: {:?}", e),
+        }
+    }
+    Ok(packs)
+}
@@ -8,7 +8,11 @@
 use amethyst::{
     core::{frame_limiter::FrameRateLimitStrategy, transform::TransformBundle},
     input::InputBundle,
-    network::simulation::tcp::TcpNetworkBundle,
+    network::simulation::{
+        laminar::{LaminarConfig, LaminarNetworkBundle, LaminarSocket},
+        tcp::TcpNetworkBundle,
+        NetworkSimulationTimeSystem,
+    },
     prelude::*,
     renderer::{
         plugins::{RenderFlat2D, RenderToWindow},
@@ -16,24 +20,33 @@ use amethyst::{
         RenderingBundle,
     },
     utils::application_root_dir,
+    ApplicationBuilder,
 };
 use std::{fs::File, net::TcpListener};
 
+use crate::capture::Recorder;
+use crate::network::Transport;
 use crate::resources::AppConfig;
 use core::time::Duration;
 use log::info;
 use ron::de::from_reader;
 use std::env;
 
+mod accounts;
+mod capture;
 mod components;
 mod constants;
 mod key_bindings;
+mod loadtest;
 mod map;
 mod mech;
 mod network;
 mod resources;
+mod secure;
+mod signing;
 mod states;
 mod systems;
+mod transfer;
 
 fn main() -> amethyst::Result<()> {
     amethyst::start_logger(Default::default());
@@ -42,7 +55,8 @@ fn main() -> amethyst::Result<()> {
     let app_root = application_root_dir()?;
     let resources = app_root.join("resources");
 
-    let input_path = format!("resources/config.ron");
+    // Running more than one server from the same place needs more than one config
+    let input_path = env::var("REALM_CONFIG").unwrap_or_else(|_| format!("resources/config.ron"));
     let f = File::open(&input_path).expect("Failed opening file");
     let config: AppConfig = match from_reader(f) {
         Ok(x) => x,
@@ -59,13 +73,34 @@ fn main() -> amethyst::Result<()> {
     } else if args[1].starts_with("s") {
         info!("Starting the server!");
         rtn = server(resources, config);
+    } else if args[1].starts_with("b") {
+        info!("Starting a bot");
+        rtn = bot(resources, config, &args[2..]);
+    } else if args[1].starts_with("l") {
+        // Lots of pretend players against a running server
+        rtn = loadtest::run(&config, &args[2..]).map_err(amethyst::Error::from_string);
+    } else if args[1].starts_with("k") {
+        // Key pair for a server that wants encryption
+        match secure::generate_key() {
+            Ok((secret, public)) => println!("secret_key: \"{}\"\nserver_key: \"{}\"", secret, public),
+            Err(e) => println!("Could not make a key: {}", e),
+        }
+        // And one for signing transfer tickets, other realms trust the public half
+        match signing::generate_key() {
+            Ok((secret, public)) => println!("realm_key: \"{}\"\ntrusted_realms: [\"{}\"]", secret, public),
+            Err(e) => println!("Could not make a realm key: {}", e),
+        }
+        rtn = Ok(());
     } else {
-        panic!("Invalid command line args. Use 's' for server or 'c' for client");
+        panic!("Invalid command line args. Use 's' for server, 'c' for client, 'b' for a bot, 'l' for a load test or 'k' for a server key");
     }
     rtn
 }
 
 fn client(resources: std::path::PathBuf, config: AppConfig) -> amethyst::Result<()> {
+    if !config.replay.is_empty() {
+        return client_replay(resources, config);
+    }
     let display_config = resources.join("display_config.ron");
     let key_bindings_config_path = resources.join("bindings.ron");
     let input_bundle = InputBundle::<key_bindings::MovementBindingTypes>::new()
@@ -80,19 +115,29 @@ fn client(resources: std::path::PathBuf, config: AppConfig) -> amethyst::Result<
                 )
                 .with_plugin(RenderFlat2D::default()),
         )?
-        .with_bundle(input_bundle)?
-        .with_bundle(TcpNetworkBundle::new(/*Some(listener)*/ None, 1048576))?
+        .with_bundle(input_bundle)?;
+
+    let game_data = client_transport(game_data, &config)?
         .with_bundle(systems::client::TcpSystemBundle)?
         .with_bundle(systems::client::WalletSystemBundle)?
-        .with_bundle(systems::client::LifeformSystemBundle)?
         .with_bundle(systems::client::PlayerSystemBundle)?
+        .with_bundle(systems::client::LifeformSystemBundle)?
         .with_bundle(systems::client::MapSystemBundle)?
         .with(systems::WalkAnimationSystem::new(), "anim_system", &[])
         .with_bundle(systems::InputSystemBundle)?
-        .with(systems::MoveSystem::new(), "move_system", &[])
+        .with(systems::InterpolateSystem::new(), "interpolate_system", &[])
+        .with(systems::MoveSystem::new(), "move_system", &["interpolate_system"])
         .with(systems::MeleeAnimationSystem::new(), "melee_system", &[]);
 
-    let mut game = Application::build(resources, states::GamePlayState { config })?
+    let game_data = match config.spectate.as_str() {
+        "" => game_data,
+        room => {
+            info!("Spectating {}", room);
+            game_data.with_bundle(systems::client::SpectatorSystemBundle)?
+        }
+    };
+
+    let mut game = capture(Application::build(resources, states::GamePlayState { config: config.clone() })?, &config)?
         .with_frame_limit(
             FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)),
             144,
@@ -104,16 +149,57 @@ fn client(resources: std::path::PathBuf, config: AppConfig) -> amethyst::Result<
 }
 
 fn server(resources: std::path::PathBuf, config: AppConfig) -> amethyst::Result<()> {
-    let listener = TcpListener::bind(config.server_ip.clone())?;
-    listener.set_nonblocking(true)?;
+    // Everything between the barriers counts towards the tick time
     let game_data = GameDataBuilder::default()
-        .with_bundle(TcpNetworkBundle::new(Some(listener), 1048576))?
+        .with(systems::server::TickStartSystem, "tick_start_system", &[])
+        .with_barrier();
+
+    let game_data = match (config.replay.as_str(), &config.transport) {
+        ("", Transport::Tcp) => {
+            let listener = TcpListener::bind(config.server_ip.clone())?;
+            listener.set_nonblocking(true)?;
+            game_data.with_bundle(TcpNetworkBundle::new(Some(listener), 1048576))?
+        }
+        ("", Transport::Laminar) => game_data
+            .with_bundle(LaminarNetworkBundle::new(Some(laminar_socket(&config.server_ip)?)))?,
+        (path, _) => replay(game_data, path)?,
+    };
+
+    let game_data = game_data
         .with_bundle(systems::server::TcpSystemBundle)?
         .with_bundle(systems::server::AuthSystemBundle)?
         .with_bundle(systems::server::LifeformSystemBundle)?
-        .with_bundle(systems::server::AiSystemBundle)?;
+        .with_bundle(systems::server::AiSystemBundle)?
+        .with_bundle(systems::server::MapSystemBundle)?;
+
+    let game_data = match (config.admin_port, config.admin_secret.as_str()) {
+        (0, _) => game_data,
+        (_, "") => return Err(amethyst::Error::from_string("admin_port is set but admin_secret is blank")),
+        (port, _) => {
+            // Never reachable from off the box
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            listener.set_nonblocking(true)?;
+            info!("Admin console on 127.0.0.1:{}", port);
+            game_data.with_bundle(systems::server::AdminSystemBundle { listener })?
+        }
+    };
+
+    let game_data = match config.metrics_port {
+        0 => game_data,
+        port => {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            listener.set_nonblocking(true)?;
+            info!("Metrics on http://127.0.0.1:{}/metrics", port);
+            game_data.with_bundle(systems::server::MetricsSystemBundle { listener })?
+        }
+    };
+
+    let game_data = game_data
+        .with_barrier()
+        .with(systems::server::TickEndSystem, "tick_end_system", &[]);
 
-    let mut game = Application::build(resources, states::ServerState { config })?
+    let accounts = accounts::Accounts::open(&config).map_err(amethyst::Error::from_string)?;
+    let mut game = capture(Application::build(resources, states::ServerState { config: config.clone() })?.with_resource(accounts), &config)?
         .with_frame_limit(
             FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)),
             144,
@@ -123,3 +209,93 @@ fn server(resources: std::path::PathBuf, config: AppConfig) -> amethyst::Result<
     game.run();
     Ok(())
 }
+
+/// Client replays only check what the `TcpSystem` sends, so no window either.
+/// Anything the game systems would have sent shows up as not sent.
+fn client_replay(resources: std::path::PathBuf, config: AppConfig) -> amethyst::Result<()> {
+    let game_data = replay(GameDataBuilder::default(), &config.replay)?
+        .with_bundle(systems::client::TcpSystemBundle)?;
+
+    let mut game = capture(Application::build(resources, states::BotState { config: config.clone() })?, &config)?
+        .with_frame_limit(
+            FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)),
+            144,
+        )
+        .build(game_data)?;
+
+    game.run();
+    Ok(())
+}
+
+/// Same protocol as the client, but no window, no input and no sprites.
+/// Takes an optional name (so a few can run at once) and script file.
+fn bot(resources: std::path::PathBuf, mut config: AppConfig, args: &[String]) -> amethyst::Result<()> {
+    if let Some(name) = args.get(0) {
+        config.player_name = name.clone();
+    }
+    let script = match args.get(1) {
+        Some(path) => systems::client::parse_script(&std::fs::read_to_string(path)?)
+            .map_err(|e| amethyst::Error::from_string(format!("{}: {}", path, e)))?,
+        None => Vec::new(), // Wander about
+    };
+
+    let game_data = client_transport(GameDataBuilder::default(), &config)?
+        .with_bundle(systems::client::TcpSystemBundle)?
+        .with_bundle(systems::client::BotSystemBundle { script })?;
+
+    let mut game = capture(Application::build(resources, states::BotState { config: config.clone() })?, &config)?
+        .with_frame_limit(
+            FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)),
+            144,
+        )
+        .build(game_data)?;
+
+    game.run();
+    Ok(())
+}
+
+/// How a client talks to the server
+fn client_transport<'a, 'b>(game_data: GameDataBuilder<'a, 'b>, config: &AppConfig) -> amethyst::Result<GameDataBuilder<'a, 'b>> {
+    Ok(match (config.replay.as_str(), &config.transport) {
+        ("", Transport::Tcp) => game_data.with_bundle(TcpNetworkBundle::new(/*Some(listener)*/ None, 1048576))?,
+        ("", Transport::Laminar) => {
+            let bind = match config.client_ip.as_str() {
+                "" => "0.0.0.0:0",
+                ip => ip,
+            };
+            game_data.with_bundle(LaminarNetworkBundle::new(Some(laminar_socket(bind)?)))?
+        }
+        (path, _) => replay(game_data, path)?,
+    })
+}
+
+/// Play a capture into the `TcpSystem` instead of talking to the network.
+/// The barrier makes sure the frame's records are on the channel before
+/// anything added after it reads them, like the network bundles do.
+fn replay<'a, 'b>(game_data: GameDataBuilder<'a, 'b>, path: &str) -> amethyst::Result<GameDataBuilder<'a, 'b>> {
+    info!("Replaying {}, not going on the network", path);
+    Ok(game_data
+        .with(NetworkSimulationTimeSystem, "replay_sim_time_system", &[])
+        .with(systems::ReplaySystem::new(path)?, "replay_system", &["replay_sim_time_system"])
+        .with_barrier())
+}
+
+/// Record every pack if the config asks for it
+fn capture<T>(app: ApplicationBuilder<T>, config: &AppConfig) -> amethyst::Result<ApplicationBuilder<T>> {
+    match config.capture.as_str() {
+        "" => Ok(app),
+        path => {
+            info!("Capturing packs to {}", path);
+            Ok(app.with_resource(Recorder::open(path)?))
+        }
+    }
+}
+
+/// Laminar drops connections that go quiet, keep them alive with heartbeats
+fn laminar_socket(addr: &str) -> amethyst::Result<LaminarSocket> {
+    let conf = LaminarConfig {
+        heartbeat_interval: Some(Duration::from_secs(1)),
+        ..LaminarConfig::default()
+    };
+    Ok(LaminarSocket::bind_with_config(addr, conf)?)
+}
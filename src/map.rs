@@ -4,6 +4,7 @@ use amethyst::{
 };
 
 extern crate tiled;
+use sha2::{Digest, Sha256};
 use std::{fs::File, io::BufReader, path::Path};
 
 use crate::components::{Monster, Orientation};
@@ -45,7 +46,7 @@ impl Room {
         let file = File::open(&Path::new(&file_name)).unwrap();
         let reader = BufReader::new(file);
         let map =
-            tiled::parse_with_path(reader, &Path::new("resources/sprites/master16.tsx")).unwrap();
+            tiled::parse_with_path(reader, &Path::new(constants::TILESET_PATH)).unwrap();
 
         let monsters = match server {
             true => Room::get_monsters(&map),
@@ -62,13 +63,15 @@ impl Room {
         }
     }
 
-    pub fn change(&mut self, map_name: String) {
-        let file = File::open(&Path::new(&map_name)).unwrap();
+    /// Load a different map. The tileset is found relative to the map file,
+    /// the way tiled saved it, so maps the server sent us work too.
+    pub fn change(&mut self, map_name: String) -> Result<(), String> {
+        let file = File::open(&Path::new(&map_name)).map_err(|e| format!("{}: {}", map_name, e))?;
         let reader = BufReader::new(file);
-        let map =
-            tiled::parse_with_path(reader, &Path::new("resources/sprites/master16.tsx")).unwrap();
+        let map = tiled::parse_with_path(reader, &Path::new(&map_name)).map_err(|e| format!("{}: {:?}", map_name, e))?;
         self.map = map;
         self.update = true;
+        Ok(())
     }
 
     // Convert world coordinates to tiled coordinates
@@ -157,6 +160,11 @@ impl Room {
     
 }
 
+/// What goes in `MapInfo`, hex sha256
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
 pub struct Adj {
     pub cur: Option<tiled::Properties>,
     pub n: Option<tiled::Properties>,
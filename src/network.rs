@@ -1,20 +1,205 @@
+use amethyst::network::simulation::DeliveryRequirement;
 use bincode;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 
-use crate::components::{Action, LifeformComponent, ItemEvent};
+use crate::components::{Action, LifeformComponent, LifeformDelta, Orientation, ItemEvent};
+use crate::constants;
+use crate::resources::ServerStats;
+use crate::transfer::TransferTicket;
 
+/// Bump this whenever the layout of `Cmd` (or anything inside it) changes.
+pub const PROTOCOL_VERSION: u32 = 17;
+
+/// Where `Connect` sits in `Cmd`, bincode writes it as the first four bytes
+const CONNECT_TAG: u32 = 1;
+
+/// Optional protocol features this build understands.
+pub const CAPABILITIES: &[&str] = &[];
+
+/// Only ever append to this enum. `Connect`, `ConnectReply` and `Greet` must
+/// keep their position so mismatched builds can still say hello to each other.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Cmd {
-    Ping,
-    Connect(String),
-    TransferMap(String),
+    Ping(u64), // Answer with a Pong carrying the same number
+    Connect(Hello),
+    TransferMap(MapInfo),
     InsertPlayer(LifeformComponent),
     InsertPlayer1(LifeformComponent),
     Action(Action),
     UpdatePlayer(LifeformComponent),
     RemovePlayer(u64),
-    // ItemEvent(ItemEvent), 
+    ConnectReply(ConnectReply),
+    UpdateLifeform(LifeformDelta),
+    Resync(u64), // Client wants the full lifeform again
+    Input(u32, Action), // Numbered player action, answered with an Ack
+    Ack(Ack),
+    Swing(u64), // Lifeform with this uid swung its sword
+    Pong(u64),
+    Session(String), // Token to put in `Hello` if we have to reconnect
+    Handshake(Vec<u8>), // Noise handshake message, only ever sent in the clear
+    Sealed(u64, Vec<u8>), // Nonce and encrypted packs, see `secure`
+    FetchFile(String), // Client is missing a map file the server told it about
+    FileChunk(FileChunk),
+    Stats, // How's the server doing, answered with StatsReply
+    StatsReply(ServerStats),
+    Transfer(String, TransferTicket), // Go to this server and show it the ticket
+    Rooms(Vec<String>), // Rooms a spectator can watch
+    Watch(String), // Spectator wants to watch a different room
+    Kicked(String), // Server is about to hang up on us, and why
+    Message(String), // Announcement for everyone
+    Greet(u32), // Client's protocol version, wants a challenge to sign before it says hello
+    Challenge(String), // Sign this in the proof, it's only good for a few seconds
+    Resynced(LifeformComponent), // Answer to Resync, reliable unlike UpdatePlayer so it can't get lost
+    // ItemEvent(ItemEvent),
+}
+
+/// First message a client sends, keep `version` as the first field
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+    pub proof: String,
+    pub resume: Option<String>, // Session token from last time, picks up the same lifeform
+    pub ticket: Option<TransferTicket>, // Coming over from another realm
+    pub spectate: Option<String>, // Watch this room instead of playing
+    pub register: bool, // Make the account before logging in
+}
+
+impl Hello {
+    pub fn new(proof: String) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+            proof,
+            resume: None,
+            ticket: None,
+            spectate: None,
+            register: false,
+        }
+    }
+
+    /// Stand-in for a hello from another protocol version. Only the version
+    /// could be read, which is all it takes to turn them away.
+    pub fn foreign(version: u32) -> Self {
+        Self {
+            version,
+            capabilities: Vec::<String>::new(),
+            proof: String::new(),
+            resume: None,
+            ticket: None,
+            spectate: None,
+            register: false,
+        }
+    }
+
+    /// Does the client say it can do this
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// Server answer to a `Cmd::Connect`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ConnectReply {
+    /// Server protocol version and the capabilities both ends share
+    Accept(u32, Vec<String>),
+    Reject(String),
+}
+
+/// The map a player is in, and what it takes to draw it. Names are paths on
+/// the server, hashes are hex sha256 of the file contents.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapInfo {
+    pub name: String,
+    pub hash: String,
+    pub tileset: String,
+    pub tileset_hash: String,
+}
+
+/// Piece of a file the server is sending down, `index` counts from 0
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileChunk {
+    pub name: String,
+    pub hash: String,
+    pub index: u32,
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+/// Where the server says a lifeform is once it has dealt with input `seq`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ack {
+    pub seq: u32,
+    pub x: f32,
+    pub y: f32,
+    pub orientation: Orientation,
+}
+
+impl Ack {
+    pub fn new(seq: u32, lifeform: &LifeformComponent) -> Self {
+        Self {
+            seq,
+            x: lifeform.x,
+            y: lifeform.y,
+            orientation: lifeform.orientation.clone(),
+        }
+    }
+}
+
+/// Which amethyst network simulation carries the packs, set in `AppConfig`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Transport {
+    Tcp,
+    Laminar, // UDP
+}
+
+impl Cmd {
+    /// How hard laminar should try to get this across. TCP ignores it.
+    pub fn delivery(&self) -> DeliveryRequirement {
+        match self {
+            // Only the newest position matters, drop anything late
+            Cmd::UpdatePlayer(_) | Cmd::UpdateLifeform(_) => DeliveryRequirement::UnreliableSequenced(None),
+            _ => DeliveryRequirement::ReliableOrdered(None),
+        }
+    }
+
+    /// Variant name, for counting traffic by kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cmd::Ping(_)           => "Ping",
+            Cmd::Connect(_)        => "Connect",
+            Cmd::TransferMap(_)    => "TransferMap",
+            Cmd::InsertPlayer(_)   => "InsertPlayer",
+            Cmd::InsertPlayer1(_)  => "InsertPlayer1",
+            Cmd::Action(_)         => "Action",
+            Cmd::UpdatePlayer(_)   => "UpdatePlayer",
+            Cmd::RemovePlayer(_)   => "RemovePlayer",
+            Cmd::ConnectReply(_)   => "ConnectReply",
+            Cmd::UpdateLifeform(_) => "UpdateLifeform",
+            Cmd::Resync(_)         => "Resync",
+            Cmd::Input(_, _)       => "Input",
+            Cmd::Ack(_)            => "Ack",
+            Cmd::Swing(_)          => "Swing",
+            Cmd::Pong(_)           => "Pong",
+            Cmd::Session(_)        => "Session",
+            Cmd::Handshake(_)      => "Handshake",
+            Cmd::Sealed(_, _)      => "Sealed",
+            Cmd::FetchFile(_)      => "FetchFile",
+            Cmd::FileChunk(_)      => "FileChunk",
+            Cmd::Stats             => "Stats",
+            Cmd::StatsReply(_)     => "StatsReply",
+            Cmd::Transfer(_, _)    => "Transfer",
+            Cmd::Rooms(_)          => "Rooms",
+            Cmd::Watch(_)          => "Watch",
+            Cmd::Kicked(_)         => "Kicked",
+            Cmd::Message(_)        => "Message",
+            Cmd::Greet(_)          => "Greet",
+            Cmd::Challenge(_)      => "Challenge",
+            Cmd::Resynced(_)       => "Resynced",
+        }
+    }
 }
 
 /// Destination
@@ -24,6 +209,7 @@ pub enum Dest {
     Ip(SocketAddr),
     AllExcept(SocketAddr),
     All,
+    Seen(u64), // Players that can see this lifeform, and the lifeform itself
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -44,11 +230,269 @@ impl Pack {
         }
     }
 
-    pub fn from_bin(bin: Vec<u8>) -> Self {
-        bincode::deserialize(&bin[..]).unwrap()
+    /// Anything can show up on the wire, so this can fail. The limit stops a
+    /// bogus length prefix from asking for more bytes than we were sent.
+    pub fn from_bin(bin: Vec<u8>) -> bincode::Result<Self> {
+        Self::read(&mut &bin[..])
+    }
+
+    /// One pack off the front of `rest`. A hello from another protocol version
+    /// won't have the same layout past its version, so that's all we read of
+    /// it, and the rest of the bytes go with it.
+    fn read(rest: &mut &[u8]) -> bincode::Result<Self> {
+        if let Some(version) = Self::foreign_version(rest) {
+            *rest = &rest[rest.len()..];
+            return Ok(Pack::new(Cmd::Connect(Hello::foreign(version)), Dest::All));
+        }
+        bincode::config()
+            .limit(rest.len() as u64)
+            .deserialize_from(rest)
+    }
+
+    /// Version of a `Connect` that isn't on ours, None for anything else
+    fn foreign_version(bin: &[u8]) -> Option<u32> {
+        let tag: u32 = bincode::deserialize(bin.get(..4)?).ok()?;
+        let version: u32 = bincode::deserialize(bin.get(4..8)?).ok()?;
+        match tag == CONNECT_TAG && version != PROTOCOL_VERSION {
+            true => Some(version),
+            false => None,
+        }
+    }
+
+    /// TCP happily glues several packs into one message, and so does `Batcher`,
+    /// pull out every one of them in the order they were sent. Stops at the first bad one.
+    pub fn from_bin_many(bin: Vec<u8>) -> Vec<bincode::Result<Self>> {
+        let mut packs = Vec::<bincode::Result<Self>>::new();
+        let mut rest = &bin[..];
+
+        while !rest.is_empty() {
+            let pack = Self::read(&mut rest);
+            let bad = pack.is_err();
+            packs.push(pack);
+            if bad {
+                break;
+            }
+        }
+        packs
+    }
+
+    pub fn to_bin(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+}
+
+/// TCP can also cut a pack in half across two reads. Keeps whatever didn't
+/// finish for each address until the rest of it shows up.
+#[derive(Default)]
+pub struct Reassembly {
+    pending: HashMap<SocketAddr, Vec<u8>>,
+}
+
+impl Reassembly {
+    /// Every whole pack `addr` has sent so far, in order. Only a pack that
+    /// can never decode comes back as an `Err`, running out of bytes isn't one.
+    pub fn feed(&mut self, addr: SocketAddr, bin: &[u8]) -> Vec<bincode::Result<Pack>> {
+        let buf = self.pending.entry(addr).or_insert_with(Vec::<u8>::new);
+        buf.extend_from_slice(bin);
+
+        let mut packs = Vec::<bincode::Result<Pack>>::new();
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let before = rest;
+            match Pack::read(&mut rest) {
+                Ok(pack) => packs.push(Ok(pack)),
+                Err(e) => {
+                    if let bincode::ErrorKind::Io(_) | bincode::ErrorKind::SizeLimit = *e {
+                        rest = before; // Not all here yet
+                        break;
+                    }
+                    packs.push(Err(e));
+                    rest = &rest[rest.len()..]; // No telling where the next one starts
+                },
+            }
+        }
+        let used = buf.len() - rest.len();
+        buf.drain(..used);
+
+        // A bogus length prefix would otherwise have us waiting forever
+        if buf.len() > constants::MAX_PENDING_BYTES {
+            buf.clear();
+            packs.push(Err(Box::new(bincode::ErrorKind::SizeLimit)));
+        }
+        packs
+    }
+
+    pub fn forget(&mut self, addr: &SocketAddr) {
+        self.pending.remove(addr);
+    }
+}
+
+/// Serialized packs for one address, sent back to back as a single message
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub delivery: DeliveryRequirement,
+    pub bin: Vec<u8>,
+    pub count: usize,
+}
+
+/// Collects everything going out over a frame so each address gets one
+/// message instead of one per pack. Packs only share a batch with the ones
+/// right next to them that want the same delivery, so order is kept.
+pub struct Batcher {
+    batches: HashMap<SocketAddr, Vec<Batch>>,
+}
+
+impl Default for Batcher {
+    fn default() -> Self {
+        Batcher::new()
+    }
+}
+
+impl Batcher {
+    pub fn new() -> Self {
+        Self {
+            batches: HashMap::<SocketAddr, Vec<Batch>>::new(),
+        }
+    }
+
+    /// Add an already serialized pack for an address
+    pub fn push(&mut self, addr: SocketAddr, pack: &Pack, bin: &[u8]) {
+        let delivery = pack.cmd.delivery();
+        let batches = self.batches.entry(addr).or_insert_with(Vec::new);
+
+        match batches.last_mut() {
+            Some(batch) if batch.delivery == delivery && batch.bin.len() + bin.len() <= constants::MAX_BATCH_BYTES => {
+                batch.bin.extend_from_slice(bin);
+                batch.count += 1;
+            },
+            _ => batches.push(Batch { delivery, bin: bin.to_vec(), count: 1 }),
+        }
+    }
+
+    /// Everything collected so far, in the order each address should get it
+    pub fn drain(&mut self) -> Vec<(SocketAddr, Batch)> {
+        self.batches
+            .drain()
+            .flat_map(|(addr, batches)| batches.into_iter().map(move |b| (addr, b)))
+            .collect()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// A few packs of different shapes, strings and nested structs included
+    fn samples() -> Vec<Pack> {
+        vec![
+            Pack::new(Cmd::Connect(Hello::new("Turnip 00ff secret".to_string())), Dest::All),
+            Pack::new(Cmd::RemovePlayer(7), Dest::Ip(addr(4000))),
+            Pack::new(Cmd::ConnectReply(ConnectReply::Reject("go away".to_string())), Dest::Room("first".to_string())),
+        ]
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10000 {
+            let len = rng.gen_range(0, 256);
+            let bin: Vec<u8> = (0..len).map(|_| rng.gen::<u8>()).collect();
+            let _ = Pack::from_bin(bin.clone());
+            let many = Pack::from_bin_many(bin);
+            // Nothing comes after a bad one
+            if let Some(bad) = many.iter().position(|p| p.is_err()) {
+                assert_eq!(bad, many.len() - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_packs_are_errors() {
+        for pack in samples() {
+            let bin = pack.to_bin().unwrap();
+            assert_eq!(Pack::from_bin(bin.clone()).unwrap().cmd, pack.cmd);
+            for cut in 0..bin.len() {
+                assert!(Pack::from_bin(bin[..cut].to_vec()).is_err(), "{:?} cut at {}", pack.cmd, cut);
+                let many = Pack::from_bin_many(bin[..cut].to_vec());
+                assert!(many.iter().all(|p| p.is_err()), "{:?} cut at {}", pack.cmd, cut);
+            }
+        }
+    }
+
+    #[test]
+    fn glued_packs_come_out_in_order() {
+        let packs = samples();
+        let bin: Vec<u8> = packs.iter().flat_map(|p| p.to_bin().unwrap()).collect();
+        let cmds: Vec<Cmd> = Pack::from_bin_many(bin).into_iter().map(|p| p.unwrap().cmd).collect();
+        assert_eq!(cmds, packs.into_iter().map(|p| p.cmd).collect::<Vec<Cmd>>());
+    }
+
+    #[test]
+    fn reassembly_waits_for_the_rest() {
+        let packs = samples();
+        let bin: Vec<u8> = packs.iter().flat_map(|p| p.to_bin().unwrap()).collect();
+        for cut in 0..=bin.len() {
+            let mut partial = Reassembly::default();
+            let mut got = partial.feed(addr(4000), &bin[..cut]);
+            got.extend(partial.feed(addr(4000), &bin[cut..]));
+            let cmds: Vec<Cmd> = got.into_iter().map(|p| p.unwrap().cmd).collect();
+            assert_eq!(cmds, packs.iter().map(|p| p.cmd.clone()).collect::<Vec<Cmd>>(), "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn reassembly_reports_garbage() {
+        let mut partial = Reassembly::default();
+        let got = partial.feed(addr(4000), &[0xff; 16]);
+        assert_eq!(got.len(), 1);
+        assert!(got[0].is_err());
+        // Doesn't hold on to it either
+        let ping = Pack::new(Cmd::Ping(1), Dest::All).to_bin().unwrap();
+        let got = partial.feed(addr(4000), &ping);
+        assert_eq!(got.into_iter().map(|p| p.unwrap().cmd).collect::<Vec<Cmd>>(), vec![Cmd::Ping(1)]);
+    }
+
+    /// What a hello looked like before it picked up resume and the rest
+    #[derive(Serialize)]
+    struct OldHello {
+        version: u32,
+        capabilities: Vec<String>,
+        proof: String,
+    }
+
+    #[derive(Serialize)]
+    enum OldCmd {
+        #[allow(dead_code)]
+        Ping(u64),
+        Connect(OldHello),
+    }
+
+    #[derive(Serialize)]
+    struct OldPack {
+        cmd: OldCmd,
+        dest: Dest,
+    }
+
+    #[test]
+    fn old_hellos_still_give_their_version() {
+        let hello = OldHello { version: 6, capabilities: Vec::<String>::new(), proof: "Turnip 00ff sig".to_string() };
+        let bin = bincode::serialize(&OldPack { cmd: OldCmd::Connect(hello), dest: Dest::All }).unwrap();
+        let expect = Cmd::Connect(Hello::foreign(6));
+
+        assert_eq!(Pack::from_bin(bin.clone()).unwrap().cmd, expect);
+        let many = Pack::from_bin_many(bin.clone());
+        assert_eq!(many.len(), 1);
+        assert_eq!(many[0].as_ref().unwrap().cmd, expect);
 
-    pub fn to_bin(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
+        // Split before the version it waits, after that it doesn't need the rest
+        let mut partial = Reassembly::default();
+        let mut got = partial.feed(addr(4000), &bin[..6]);
+        got.extend(partial.feed(addr(4000), &bin[6..]));
+        assert_eq!(got.into_iter().map(|p| p.unwrap().cmd).collect::<Vec<Cmd>>(), vec![expect]);
     }
 }
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    components::Action,
+    constants,
+};
+
+/// Which cooldown an action counts against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionKind {
+    Move,
+    Turn,
+    Melee,
+    Outfit,
+}
+
+impl ActionKind {
+    /// None for actions that don't do anything on the server yet
+    pub fn of(act: &Action) -> Option<Self> {
+        match act {
+            Action::Move(_) => Some(ActionKind::Move),
+            Action::Rotate(_) => Some(ActionKind::Turn),
+            Action::Melee | Action::Attack(_) => Some(ActionKind::Melee),
+            Action::ChangeOutfit(_) => Some(ActionKind::Outfit),
+            _ => None,
+        }
+    }
+
+    fn cooldown_ms(&self) -> u64 {
+        match self {
+            ActionKind::Move => constants::MOVE_COOLDOWN_MS,
+            ActionKind::Turn => constants::MOVE_COOLDOWN_MS,
+            ActionKind::Melee => constants::MELEE_COOLDOWN_MS,
+            ActionKind::Outfit => constants::OUTFIT_COOLDOWN_MS,
+        }
+    }
+}
+
+/// Server side rate limit on client actions. Each lifeform gets one action of
+/// a kind per cooldown, with a little slack so packets that bunch up on the
+/// way over don't count against them.
+pub struct ActionBudget {
+    next: HashMap<(u64, ActionKind), Instant>, // When the next action is due
+    violations: HashMap<u64, (u32, Instant)>,  // Recent violations and when the last one was
+    pub total_violations: u64,
+}
+
+impl Default for ActionBudget {
+    fn default() -> Self {
+        ActionBudget::new()
+    }
+}
+
+impl ActionBudget {
+    pub fn new() -> Self {
+        Self {
+            next: HashMap::<(u64, ActionKind), Instant>::new(),
+            violations: HashMap::<u64, (u32, Instant)>::new(),
+            total_violations: 0,
+        }
+    }
+
+    /// Spend from the budget, false if they're going too fast
+    pub fn allow(&mut self, uid: u64, act: &Action, now: Instant) -> bool {
+        let kind = match ActionKind::of(act) {
+            Some(kind) => kind,
+            None => return true,
+        };
+        let cooldown = Duration::from_millis(kind.cooldown_ms());
+        let slack = cooldown * (constants::ACTION_BURST - 1);
+
+        let due = match self.next.get(&(uid, kind)) {
+            Some(due) if *due > now => *due,
+            _ => now,
+        };
+        if due.duration_since(now) > slack {
+            return false;
+        }
+        self.next.insert((uid, kind), due + cooldown);
+        true
+    }
+
+    /// Count a violation, returns how many they've racked up without a break
+    pub fn violation(&mut self, uid: u64, now: Instant) -> u32 {
+        self.total_violations += 1;
+        let entry = self.violations.entry(uid).or_insert((0, now));
+        if now.duration_since(entry.1).as_millis() >= constants::RATE_VIOLATION_WINDOW_MS {
+            entry.0 = 0; // Behaved for a while, start again
+        }
+        entry.0 += 1;
+        entry.1 = now;
+        entry.0
+    }
+
+    pub fn forget(&mut self, uid: u64) {
+        self.next.retain(|(id, _), _| *id != uid);
+        self.violations.remove(&uid);
+    }
+}
@@ -1,18 +1,73 @@
 use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+use crate::accounts::AuthBackend;
+use crate::network::Transport;
+use crate::resources::DuplicateLogin;
+use crate::transfer::{EntryPoint, Portal};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct AppConfig {
-    pub server_ip:   String,
-    pub client_ip:   String,
-    pub player_name: String,
+    pub server_ip:       String,
+    pub client_ip:       String,    // Local address to bind to when using laminar
+    pub player_name:     String,
+    pub inbound_budget:  usize,     // Max packs the client handles per frame, 0 is no limit
+    pub transport:       Transport,
+    pub idle_timeout_ms: u64,       // Drop anyone we haven't heard from in this long
+    pub resume_grace_ms: u64,       // How long a dropped player waits around for a reconnect
+    pub encrypt:         bool,      // Client: encrypt the connection. Server: refuse anyone that doesn't
+    pub server_key:      String,    // Client only, hex public key the server has to have, blank trusts any
+    pub secret_key:      String,    // Server only, hex private key, blank makes a new one every start
+    pub capture:         String,    // File to record every pack to, blank is off
+    pub replay:          String,    // Capture to play back instead of going on the network, blank is off
+    pub realm_key:       String,    // Server only, hex ed25519 key transfer tickets are signed with
+    pub trusted_realms:  Vec<String>, // Server only, hex public keys of realms we take tickets from
+    pub portals:         Vec<Portal>,
+    pub entry_points:    Vec<EntryPoint>,
+    pub spectate:        String,    // Client only, room to watch instead of playing, blank plays
+    pub admin_port:      u16,       // Server only, localhost port for the admin console, 0 is off
+    pub admin_secret:    String,    // Server only, what the admin console has to say first
+    pub metrics_port:    u16,       // Server only, localhost port for Prometheus to scrape, 0 is off
+    pub player_key:      String,    // Client only, file with our hex signing key, made on the first run
+    pub player_keys:     HashMap<String, String>, // Server only, player name to hex public key
+    pub duplicate_login: DuplicateLogin, // Server only, what happens when a player logs in twice
+    pub auth:            AuthBackend, // Server only, how players prove who they are
+    pub accounts_file:   String,    // Server only, where the Passwords backend keeps accounts
+    pub password:        String,    // Client only, log in with this instead of player_key, blank uses the key
+    pub register:        bool,      // Client only, make the account on this login
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self{ 
-            server_ip:    "127.0.0.1:3456".to_string(),
-            client_ip:    "127.0.0.1:3455".to_string(),
-            player_name:  "Turnip".to_string(),
+            server_ip:       "127.0.0.1:3456".to_string(),
+            client_ip:       "127.0.0.1:3455".to_string(),
+            player_name:     "Turnip".to_string(),
+            inbound_budget:  64,
+            transport:       Transport::Tcp,
+            idle_timeout_ms: 10000,
+            resume_grace_ms: 30000,
+            encrypt:         false,
+            server_key:      "".to_string(),
+            secret_key:      "".to_string(),
+            capture:         "".to_string(),
+            replay:          "".to_string(),
+            realm_key:       "".to_string(),
+            trusted_realms:  Vec::<String>::new(),
+            portals:         Vec::<Portal>::new(),
+            entry_points:    Vec::<EntryPoint>::new(),
+            spectate:        "".to_string(),
+            admin_port:      0,
+            admin_secret:    "".to_string(),
+            metrics_port:    0,
+            player_key:      "resources/player.key".to_string(),
+            player_keys:     HashMap::<String, String>::new(),
+            duplicate_login: DuplicateLogin::HandOver,
+            auth:            AuthBackend::Keys,
+            accounts_file:   "resources/accounts.txt".to_string(),
+            password:        "".to_string(),
+            register:        false,
         } 
     }
 }
@@ -0,0 +1,72 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::constants;
+
+/// Random challenges handed to connections for their login proof to sign.
+/// Each one is good for one login, from the connection it was given to,
+/// for `CHALLENGE_TTL_MS`.
+pub struct Challenges {
+    issued: HashMap<SocketAddr, (String, Instant)>,
+    used: HashMap<String, Instant>, // Answered already, kept until they'd have expired anyway
+    replayed: HashMap<SocketAddr, VecDeque<String>>, // Handed out in a capture, given out again in order
+}
+
+impl Default for Challenges {
+    fn default() -> Self {
+        Challenges::new()
+    }
+}
+
+impl Challenges {
+    pub fn new() -> Self {
+        Self {
+            issued: HashMap::<SocketAddr, (String, Instant)>::new(),
+            used: HashMap::<String, Instant>::new(),
+            replayed: HashMap::<SocketAddr, VecDeque<String>>::new(),
+        }
+    }
+
+    /// Only for replaying a capture, gives out the challenges it has so the
+    /// logins in it still work. A live server always starts from `new`.
+    pub fn from_capture(captured: HashMap<SocketAddr, VecDeque<String>>) -> Self {
+        Self {
+            replayed: captured,
+            ..Challenges::new()
+        }
+    }
+
+    /// A new challenge for this connection, replacing any it had
+    pub fn issue(&mut self, addr: SocketAddr, now: Instant) -> String {
+        self.issued.retain(|_, (_, at)| !expired(*at, now));
+        let challenge = match self.replayed.get_mut(&addr).and_then(|q| q.pop_front()) {
+            Some(challenge) => challenge,
+            None => hex::encode(rand::random::<[u8; 16]>()),
+        };
+        self.issued.insert(addr, (challenge.clone(), now));
+        challenge
+    }
+
+    /// Use up the challenge a proof signed. Whatever happens the connection
+    /// needs a new one for its next try.
+    pub fn answer(&mut self, addr: SocketAddr, challenge: &str, now: Instant) -> Result<(), String> {
+        self.used.retain(|_, at| !expired(*at, now));
+        if self.used.contains_key(challenge) {
+            return Err("Challenge has already been used".to_string());
+        }
+        match self.issued.remove(&addr) {
+            None => Err("No challenge was given to this connection".to_string()),
+            Some((issued, _)) if issued != challenge => Err("Proof is for a different challenge".to_string()),
+            Some((_, at)) if expired(at, now) => Err("Challenge has expired".to_string()),
+            Some((issued, _)) => {
+                self.used.insert(issued, now);
+                Ok(())
+            },
+        }
+    }
+}
+
+fn expired(at: Instant, now: Instant) -> bool {
+    now.duration_since(at).as_millis() >= constants::CHALLENGE_TTL_MS
+}
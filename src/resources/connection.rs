@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use crate::transfer::TransferTicket;
+
+/// Where the client is with the server. Filled in by the client TcpSystem.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Accepted,
+    Rejected(String),
+}
+
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    pub server_version: Option<u32>,
+    pub capabilities: Vec<String>,
+    pub ping: Option<Duration>, // Last round trip to the server
+    pub resume: Option<String>, // Session token, sent with the next hello
+    pub ticket: Option<TransferTicket>, // From the realm that sent us here, until we're let in
+    pub rooms: Vec<String>,             // Spectators only, what there is to watch
+    pub watching: Option<String>,       // Spectators only, the room we're in
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        ConnectionStatus::new()
+    }
+}
+
+impl ConnectionStatus {
+    pub fn new() -> Self {
+        Self {
+            state: ConnectionState::Connecting,
+            server_version: None,
+            capabilities: Vec::<String>::new(),
+            ping: None,
+            resume: None,
+            ticket: None,
+            rooms: Vec::<String>::new(),
+            watching: None,
+        }
+    }
+
+    /// Did the server agree to use this capability
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
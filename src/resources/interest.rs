@@ -0,0 +1,135 @@
+use crate::{
+    components::{LifeformComponent, LifeformType},
+    constants,
+    network::{Cmd, Dest, Pack},
+    resources::LifeformList,
+};
+
+use std::collections::{HashMap, HashSet};
+
+/// Interest management. Keeps track of which players can see which
+/// lifeforms so updates only go to the people that care about them.
+pub struct Interest {
+    seen: HashMap<u64, HashSet<u64>>,     // Player -> lifeforms they can see
+    watchers: HashMap<u64, HashSet<u64>>, // Lifeform -> players that can see it
+}
+
+impl Default for Interest {
+    fn default() -> Self {
+        Interest::new()
+    }
+}
+
+impl Interest {
+    pub fn new() -> Self {
+        Self {
+            seen: HashMap::<u64, HashSet<u64>>::new(),
+            watchers: HashMap::<u64, HashSet<u64>>::new(),
+        }
+    }
+
+    pub fn sees(&self, observer: u64, subject: u64) -> bool {
+        match self.seen.get(&observer) {
+            Some(subjects) => subjects.contains(&subject),
+            None => false,
+        }
+    }
+
+    /// Players that can currently see this lifeform
+    pub fn watchers(&self, subject: u64) -> Vec<u64> {
+        match self.watchers.get(&subject) {
+            Some(observers) => observers.iter().cloned().collect(),
+            None => Vec::<u64>::new(),
+        }
+    }
+
+    fn show(&mut self, observer: u64, subject: u64) {
+        self.seen.entry(observer).or_insert_with(HashSet::new).insert(subject);
+        self.watchers.entry(subject).or_insert_with(HashSet::new).insert(observer);
+    }
+
+    fn hide(&mut self, observer: u64, subject: u64) {
+        if let Some(subjects) = self.seen.get_mut(&observer) {
+            subjects.remove(&subject);
+        }
+        if let Some(observers) = self.watchers.get_mut(&subject) {
+            observers.remove(&observer);
+        }
+    }
+
+    /// Lifeform is gone, stop tracking it both ways
+    pub fn forget(&mut self, uid: u64) {
+        self.blind(uid);
+        if let Some(observers) = self.watchers.remove(&uid) {
+            for observer in observers {
+                if let Some(subjects) = self.seen.get_mut(&observer) {
+                    subjects.remove(&uid);
+                }
+            }
+        }
+    }
+
+    /// Stop tracking what a player can see, without touching who can see them.
+    /// The next refresh sends them everything in view again.
+    pub fn blind(&mut self, uid: u64) {
+        if let Some(subjects) = self.seen.remove(&uid) {
+            for subject in subjects {
+                if let Some(observers) = self.watchers.get_mut(&subject) {
+                    observers.remove(&uid);
+                }
+            }
+        }
+    }
+
+    /// A lifeform showed up or moved. Work out who can now see it, and what it
+    /// can now see, and return the inserts and removes that need to go out.
+    pub fn refresh(&mut self, lifeform: &LifeformComponent, pl: &LifeformList) -> Vec<Pack> {
+        let mut packs = Vec::<Pack>::new();
+
+        // Who can see the lifeform
+        if let Some(players) = pl.in_room(&lifeform.room, LifeformType::Player) {
+            for id in players {
+                if let Some(player) = pl.get_from_id(*id) {
+                    if player.id() != lifeform.id() {
+                        self.look(&player, lifeform, &mut packs);
+                    }
+                }
+            }
+        }
+
+        // What the lifeform can see, only players care
+        if lifeform.kind == LifeformType::Player {
+            for kind in vec![LifeformType::Player, LifeformType::Monster] {
+                if let Some(others) = pl.in_room(&lifeform.room, kind) {
+                    for id in others {
+                        if let Some(other) = pl.get_from_id(*id) {
+                            if other.id() != lifeform.id() {
+                                self.look(lifeform, &other, &mut packs);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        packs
+    }
+
+    /// Things come into view at the edge of vision and leave a bit past it,
+    /// so a lifeform pacing on the line doesn't flicker in and out.
+    fn look(&mut self, observer: &LifeformComponent, subject: &LifeformComponent, packs: &mut Vec<Pack>) {
+        let ip = match observer.ip {
+            Some(ip) => ip,
+            None => return,
+        };
+        let seen = self.sees(observer.id(), subject.id());
+
+        if !seen && observer.in_range(subject) {
+            self.show(observer.id(), subject.id());
+            packs.push(Pack::new(Cmd::InsertPlayer(subject.clone()), Dest::Ip(ip)));
+        }
+        else if seen && !observer.in_view(subject, constants::VISION_MARGIN) {
+            self.hide(observer.id(), subject.id());
+            packs.push(Pack::new(Cmd::RemovePlayer(subject.id()), Dest::Ip(ip)));
+        }
+    }
+}
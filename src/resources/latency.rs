@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Round trip times to each client, measured by the server TcpSystem pings
+pub struct Latency {
+    rtt: HashMap<SocketAddr, Duration>,
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::new()
+    }
+}
+
+impl Latency {
+    pub fn new() -> Self {
+        Self {
+            rtt: HashMap::<SocketAddr, Duration>::new(),
+        }
+    }
+
+    pub fn get(&self, addr: SocketAddr) -> Option<Duration> {
+        self.rtt.get(&addr).cloned()
+    }
+
+    pub fn set(&mut self, addr: SocketAddr, rtt: Duration) {
+        self.rtt.insert(addr, rtt);
+    }
+
+    pub fn remove(&mut self, addr: SocketAddr) {
+        self.rtt.remove(&addr);
+    }
+}
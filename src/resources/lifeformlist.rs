@@ -80,25 +80,28 @@ impl LifeformList {
     }
 
     pub fn remove_with_id(&mut self, id: u64) {
-        self.remove(*self.ids.get(&id).unwrap()); 
+        if let Some(slice) = self.ids.get(&id) {
+            self.remove(*slice);
+        }
     }
     
     pub fn get_from_ip(&mut self, ip: SocketAddr) -> Option<LifeformComponent> {
-        self.list[*self.ips.get(&ip).unwrap()].clone()
+        match self.ips.get(&ip) {
+            Some(slice) => self.list[*slice].clone(),
+            None => None,
+        }
     }
     
     /// Get all the IPs in a certain room
     pub fn ip_in_room(&mut self, room: &String) -> Vec<SocketAddr> {
         let mut ip = Vec::<SocketAddr>::new();
 
-        for lifeform in &self.list {
-            match lifeform {
-                Some(lf) => {
-                    if lf.room == *room && lf.kind == LifeformType::Player {
-                        ip.push(lf.ip());
-                    }
-                },
-                None => {}
+        if let Some(players) = self.players.get(room) {
+            for id in players {
+                // Players that dropped and might come back have no ip
+                if let Some(addr) = self.get_from_id(*id).and_then(|lf| lf.ip) {
+                    ip.push(addr);
+                }
             }
         }
         ip
@@ -114,10 +117,35 @@ impl LifeformList {
     }
    
     pub fn get_from_id(&self, id: u64) -> Option<LifeformComponent> {
-        self.list[*self.ids.get(&id).unwrap()].clone()
+        match self.ids.get(&id) {
+            Some(slice) => self.list[*slice].clone(),
+            None => None,
+        }
     }
 
-    
+
+    /// The player logged in with this name, connected or held
+    pub fn get_player_by_name(&self, name: &str) -> Option<LifeformComponent> {
+        self.list.iter()
+            .flatten()
+            .find(|lf| lf.kind == LifeformType::Player && lf.name == name)
+            .cloned()
+    }
+
+    /// Point a lifeform at a different connection, or none at all
+    pub fn rebind(&mut self, id: u64, ip: Option<SocketAddr>) {
+        if let Some(slice) = self.ids.get(&id).cloned() {
+            if let Some(lifeform) = self.list[slice].as_mut() {
+                if let Some(old) = lifeform.ip {
+                    self.ips.remove(&old);
+                }
+                lifeform.ip = ip;
+                if let Some(new) = ip {
+                    self.ips.insert(new, slice);
+                }
+            }
+        }
+    }
 
     pub fn replace(&mut self, player: LifeformComponent) {
         let id = player.id(); 
@@ -1,10 +1,21 @@
-use crate::map::Room;
+use crate::components::Monster;
+use crate::constants;
+use crate::map::{content_hash, Room};
+use crate::network::MapInfo;
+use log::error;
 use std::collections::HashMap;
 
+/// A file clients can ask for, kept in memory with its hash
+pub struct SharedFile {
+    pub bytes: Vec<u8>,
+    pub hash: String,
+}
+
 // MapList for the server
 pub struct MapList {
     pub list: HashMap<String, Room>,
     maps: Vec<String>,
+    files: HashMap<String, SharedFile>, // Maps and the tileset, by name
 }
 
 impl Default for MapList {
@@ -18,24 +29,66 @@ impl MapList {
         Self {
             list: HashMap::<String, Room>::new(),
             maps: Vec::<String>::new(),
+            files: HashMap::<String, SharedFile>::new(),
         }
     }
     
     pub fn add(&mut self, file_name: String) {
         self.maps.push(file_name.clone()); 
-        self.list.insert(file_name.clone(), Room::new(file_name, true));
+        self.list.insert(file_name.clone(), Room::new(file_name.clone(), true));
+
+        // Clients without the map can download it
+        self.share(file_name);
+        if !self.files.contains_key(constants::TILESET_PATH) {
+            self.share(constants::TILESET_PATH.to_string());
+        }
     }
     
+    fn share(&mut self, file_name: String) {
+        match std::fs::read(&file_name) {
+            Ok(bytes) => {
+                let hash = content_hash(&bytes);
+                self.files.insert(file_name, SharedFile { bytes, hash });
+            },
+            Err(e) => error!("Could not read {} to share it: {}", file_name, e),
+        }
+    }
+
     pub fn get(&self, file_name: &String) -> Option<&Room> {
         self.list.get(file_name)
     }
 
+    /// Only ever hands out files we loaded ourselves, never a path a client made up
+    pub fn file(&self, file_name: &str) -> Option<&SharedFile> {
+        self.files.get(file_name)
+    }
+
+    /// What a client needs to know to draw this map
+    pub fn info(&self, file_name: &String) -> Option<MapInfo> {
+        let map = self.files.get(file_name)?;
+        let tileset = self.files.get(constants::TILESET_PATH)?;
+        Some(MapInfo {
+            name: file_name.clone(),
+            hash: map.hash.clone(),
+            tileset: constants::TILESET_PATH.to_string(),
+            tileset_hash: tileset.hash.clone(),
+        })
+    }
+
     pub fn get_rooms(&self) -> Vec<String> {
         let mut maps = Vec::<String>::new();
 
         for map in self.maps.iter() {
-            maps.push(map.clone()); 
+            maps.push(map.clone());
         }
         maps
     }
+
+    /// First monster with this name on any map, to copy from
+    pub fn template(&self, name: &str) -> Option<&Monster> {
+        self.maps.iter()
+            .filter_map(|map| self.list.get(map))
+            .flat_map(|room| room.monsters.iter())
+            .find(|monster| monster.name == name)
+    }
 }
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use crate::network::Cmd;
+use crate::resources::ActionKind;
+
+/// Packs and bytes one kind of `Cmd` has moved
+#[derive(Default, Debug, Clone)]
+pub struct Traffic {
+    pub packs_in: u64,
+    pub packs_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// Counters the metrics endpoint reports that don't fit in `ServerStats`.
+/// Bytes are the pack on its own, before batching and encryption.
+#[derive(Default, Debug)]
+pub struct Metrics {
+    pub traffic: HashMap<&'static str, Traffic>, // By Cmd variant
+    pub ai_events: HashMap<ActionKind, u64>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn incoming(&mut self, cmd: &Cmd, bytes: u64) {
+        let traffic = self.traffic.entry(cmd.name()).or_default();
+        traffic.packs_in += 1;
+        traffic.bytes_in += bytes;
+    }
+
+    pub fn outgoing(&mut self, cmd: &Cmd, bytes: u64) {
+        let traffic = self.traffic.entry(cmd.name()).or_default();
+        traffic.packs_out += 1;
+        traffic.bytes_out += bytes;
+    }
+
+    pub fn ai(&mut self, kind: ActionKind) {
+        *self.ai_events.entry(kind).or_insert(0) += 1;
+    }
+}
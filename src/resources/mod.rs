@@ -22,3 +22,39 @@ pub use self::lifeform_uid::LifeformUID;
 
 mod items;
 pub use self::items::Items;
+
+mod connection;
+pub use self::connection::ConnectionStatus;
+pub use self::connection::ConnectionState;
+
+mod net_stats;
+pub use self::net_stats::NetStats;
+
+mod interest;
+pub use self::interest::Interest;
+
+mod latency;
+pub use self::latency::Latency;
+
+mod sessions;
+pub use self::sessions::Sessions;
+pub use self::sessions::DuplicateLogin;
+
+mod action_budget;
+pub use self::action_budget::ActionBudget;
+pub use self::action_budget::ActionKind;
+
+mod server_stats;
+pub use self::server_stats::ServerStats;
+
+mod spent_tickets;
+pub use self::spent_tickets::SpentTickets;
+
+mod spectators;
+pub use self::spectators::Spectators;
+
+mod metrics;
+pub use self::metrics::Metrics;
+
+mod challenges;
+pub use self::challenges::Challenges;
@@ -0,0 +1,18 @@
+/// Counters for the client inbound pack queue
+
+#[derive(Default, Debug, Clone)]
+pub struct NetStats {
+    pub received: u64,
+    pub dispatched: u64,
+    pub queue_depth: usize,      // Packs left waiting after this frame
+    pub peak_queue_depth: usize, // Deepest the queue has been
+}
+
+impl NetStats {
+    pub fn queued(&mut self, depth: usize) {
+        self.queue_depth = depth;
+        if depth > self.peak_queue_depth {
+            self.peak_queue_depth = depth;
+        }
+    }
+}
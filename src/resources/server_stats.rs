@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Running totals about how hard the server is working. Ticks are the time
+/// the dispatcher spends on a frame, not counting the frame limiter's sleep.
+/// Clients can ask for a copy with `Cmd::Stats`.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ServerStats {
+    pub ticks: u64,
+    pub tick_total_us: u64,
+    pub tick_max_us: u64,
+    pub packs_in: u64,
+    pub packs_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub clients: u32,
+    pub lifeforms: u32,
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tick(&mut self, took: Duration) {
+        let us = took.as_micros() as u64;
+        self.ticks += 1;
+        self.tick_total_us += us;
+        if us > self.tick_max_us {
+            self.tick_max_us = us;
+        }
+    }
+}
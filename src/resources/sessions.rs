@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// What to do when someone logs in as a player that's already on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DuplicateLogin {
+    KickOld,   // Old connection goes, and so does its lifeform
+    RejectNew, // First one in keeps it
+    HandOver,  // Old connection goes, the new one carries on with the lifeform
+}
+
+/// Resume tokens handed out at login, and the players whose connection
+/// dropped that we're hanging on to in case they come back.
+pub struct Sessions {
+    tokens: HashMap<String, u64>, // Token -> lifeform uid
+    held: HashMap<u64, Instant>,  // Lifeform uid -> when they dropped
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Sessions::new()
+    }
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self {
+            tokens: HashMap::<String, u64>::new(),
+            held: HashMap::<u64, Instant>::new(),
+        }
+    }
+
+    /// New token for a lifeform, any old one stops working
+    pub fn issue(&mut self, uid: u64) -> String {
+        self.revoke(uid);
+        let token = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+        self.tokens.insert(token.clone(), uid);
+        token
+    }
+
+    /// Connection is gone, start the grace period
+    pub fn hold(&mut self, uid: u64, now: Instant) {
+        self.held.insert(uid, now);
+    }
+
+    pub fn is_held(&self, uid: u64) -> bool {
+        self.held.contains_key(&uid)
+    }
+
+    /// The lifeform a token belongs to, as long as it's waiting on us
+    pub fn holding(&self, token: &str) -> Option<u64> {
+        match self.tokens.get(token) {
+            Some(uid) if self.is_held(*uid) => Some(*uid),
+            _ => None,
+        }
+    }
+
+    /// Stop holding the lifeform. The token is used up, issue a new one.
+    pub fn resume(&mut self, token: &str) -> Option<u64> {
+        let uid = self.holding(token)?;
+        self.held.remove(&uid);
+        self.tokens.remove(token);
+        Some(uid)
+    }
+
+    /// Lifeform is gone for good
+    pub fn revoke(&mut self, uid: u64) {
+        self.tokens.retain(|_, id| *id != uid);
+        self.held.remove(&uid);
+    }
+
+    /// Players that have been gone longer than the grace period, forgotten here
+    pub fn expired(&mut self, now: Instant, grace_ms: u64) -> Vec<u64> {
+        let gone: Vec<u64> = self.held.iter()
+            .filter(|(_, dropped)| now.duration_since(**dropped).as_millis() >= grace_ms as u128)
+            .map(|(uid, _)| *uid)
+            .collect();
+
+        for uid in gone.iter() {
+            self.revoke(*uid);
+        }
+        gone
+    }
+}
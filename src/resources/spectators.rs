@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Connections watching a room without a lifeform of their own. They get
+/// everything that happens in the room, not just what's in view.
+pub struct Spectators {
+    watching: HashMap<SocketAddr, String>, // Connection -> room
+}
+
+impl Default for Spectators {
+    fn default() -> Self {
+        Spectators::new()
+    }
+}
+
+impl Spectators {
+    pub fn new() -> Self {
+        Self {
+            watching: HashMap::<SocketAddr, String>::new(),
+        }
+    }
+
+    pub fn watch(&mut self, addr: SocketAddr, room: String) {
+        self.watching.insert(addr, room);
+    }
+
+    /// Returns false if they weren't spectating
+    pub fn stop(&mut self, addr: SocketAddr) -> bool {
+        self.watching.remove(&addr).is_some()
+    }
+
+    pub fn room_of(&self, addr: SocketAddr) -> Option<&String> {
+        self.watching.get(&addr)
+    }
+
+    /// Everyone watching a room
+    pub fn watching(&self, room: &str) -> Vec<SocketAddr> {
+        self.watching.iter()
+            .filter(|(_, r)| r.as_str() == room)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+/// Transfer tickets that have already been used, kept until they would
+/// have expired anyway
+pub struct SpentTickets {
+    spent: HashMap<u64, u64>, // Ticket id -> when it expires
+}
+
+impl Default for SpentTickets {
+    fn default() -> Self {
+        SpentTickets::new()
+    }
+}
+
+impl SpentTickets {
+    pub fn new() -> Self {
+        Self {
+            spent: HashMap::<u64, u64>::new(),
+        }
+    }
+
+    /// Use up a ticket, false if it was used already
+    pub fn spend(&mut self, id: u64, expires: u64, now: u64) -> bool {
+        self.spent.retain(|_, until| *until >= now);
+        if self.spent.contains_key(&id) {
+            return false;
+        }
+        self.spent.insert(id, expires);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_once() {
+        let mut spent = SpentTickets::new();
+        assert!(spent.spend(1, 130, 100));
+        assert!(!spent.spend(1, 130, 110));
+        assert!(spent.spend(2, 130, 110));
+        assert!(!spent.spend(2, 130, 130));
+    }
+
+    #[test]
+    fn forgotten_once_expired() {
+        let mut spent = SpentTickets::new();
+        assert!(spent.spend(1, 130, 100));
+        // `check` turns it away by now, no need to remember it
+        assert!(spent.spend(2, 200, 131));
+        assert!(spent.spend(1, 130, 131));
+        assert!(!spent.spend(2, 200, 150));
+    }
+}
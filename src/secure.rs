@@ -0,0 +1,153 @@
+use amethyst::network::simulation::DeliveryRequirement;
+use log::info;
+use snow::{Builder, HandshakeState, StatelessTransportState};
+
+use crate::network::{Cmd, Dest, Pack};
+
+/// Noise NX: the client has no key of its own, the server sends its static
+/// key during the handshake so the client can check it against a pinned one.
+const NOISE_PARAMS: &str = "Noise_NX_25519_ChaChaPoly_BLAKE2s";
+const MAX_MESSAGE: usize = 65535;
+const TAG_LEN: usize = 16;
+
+/// Fresh server key pair as hex, (secret, public)
+pub fn generate_key() -> Result<(String, String), String> {
+    let keys = builder()?.generate_keypair().map_err(|e| format!("{:?}", e))?;
+    Ok((hex::encode(keys.private), hex::encode(keys.public)))
+}
+
+fn builder<'a>() -> Result<Builder<'a>, String> {
+    let params = NOISE_PARAMS.parse().map_err(|e| format!("{:?}", e))?;
+    Ok(Builder::new(params))
+}
+
+/// Encrypted session with the other end. Starts out in the handshake, then
+/// seals and opens payloads. Every sealed message carries its own nonce since
+/// laminar can drop them. The low bit of the nonce says which laminar stream
+/// it went on, each stream arrives in order so its nonces only ever go up.
+pub struct SecureChannel {
+    handshake: Option<HandshakeState>,
+    transport: Option<StatelessTransportState>,
+    pinned: Option<Vec<u8>>,   // Server key the client expects, None trusts anyone
+    sent: u64,                 // Messages sealed so far
+    opened: [Option<u64>; 2],  // Last nonce opened on each stream
+}
+
+impl SecureChannel {
+    /// Client side, returns the channel and the first handshake pack to send
+    pub fn initiator(server_key: &str) -> Result<(Self, Pack), String> {
+        let pinned = match server_key {
+            "" => None,
+            key => Some(hex::decode(key).map_err(|e| format!("Bad server key: {}", e))?),
+        };
+        let mut handshake = builder()?.build_initiator().map_err(|e| format!("{:?}", e))?;
+
+        let mut buf = vec![0u8; MAX_MESSAGE];
+        let len = handshake.write_message(&[], &mut buf).map_err(|e| format!("{:?}", e))?;
+        buf.truncate(len);
+
+        let channel = Self::new(handshake, pinned);
+        Ok((channel, Pack::new(Cmd::Handshake(buf), Dest::All)))
+    }
+
+    /// Server side, answers the client's first handshake message
+    pub fn responder(secret_key: &[u8], msg: &[u8]) -> Result<(Self, Pack), String> {
+        let mut handshake = builder()?
+            .local_private_key(secret_key)
+            .build_responder()
+            .map_err(|e| format!("{:?}", e))?;
+
+        let mut buf = vec![0u8; MAX_MESSAGE];
+        handshake.read_message(msg, &mut buf).map_err(|e| format!("{:?}", e))?;
+        let len = handshake.write_message(&[], &mut buf).map_err(|e| format!("{:?}", e))?;
+        buf.truncate(len);
+
+        let mut channel = Self::new(handshake, None);
+        channel.finish()?;
+        Ok((channel, Pack::new(Cmd::Handshake(buf), Dest::All)))
+    }
+
+    fn new(handshake: HandshakeState, pinned: Option<Vec<u8>>) -> Self {
+        Self {
+            handshake: Some(handshake),
+            transport: None,
+            pinned,
+            sent: 0,
+            opened: [None, None],
+        }
+    }
+
+    /// Client side, the server's answer to our handshake
+    pub fn read_handshake(&mut self, msg: &[u8]) -> Result<(), String> {
+        let handshake = match self.handshake.as_mut() {
+            Some(handshake) => handshake,
+            None => return Err("Handshake already done".to_string()),
+        };
+
+        let mut buf = vec![0u8; MAX_MESSAGE];
+        handshake.read_message(msg, &mut buf).map_err(|e| format!("{:?}", e))?;
+
+        let key = handshake.get_remote_static().map(|k| k.to_vec()).unwrap_or_default();
+        match &self.pinned {
+            Some(pinned) if *pinned != key => return Err("Server key does not match the pinned one".to_string()),
+            Some(_) => (),
+            None => info!("Server key is {}, pin it with server_key in the config", hex::encode(&key)),
+        }
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        if let Some(handshake) = self.handshake.take() {
+            let transport = handshake.into_stateless_transport_mode().map_err(|e| format!("{:?}", e))?;
+            self.transport = Some(transport);
+        }
+        Ok(())
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Wrap up a message (usually a batch of packs) for the wire
+    pub fn seal(&mut self, plain: &[u8], delivery: DeliveryRequirement) -> Result<Pack, String> {
+        let transport = match &self.transport {
+            Some(transport) => transport,
+            None => return Err("Handshake not done".to_string()),
+        };
+
+        let nonce = self.sent << 1 | stream(delivery);
+        let mut buf = vec![0u8; plain.len() + TAG_LEN];
+        let len = transport.write_message(nonce, plain, &mut buf).map_err(|e| format!("{:?}", e))?;
+        buf.truncate(len);
+        self.sent += 1;
+        Ok(Pack::new(Cmd::Sealed(nonce, buf), Dest::All))
+    }
+
+    /// Unwrap a sealed message, fails if it was tampered with or replayed
+    pub fn open(&mut self, nonce: u64, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        let transport = match &self.transport {
+            Some(transport) => transport,
+            None => return Err("Handshake not done".to_string()),
+        };
+        let last = &self.opened[(nonce & 1) as usize];
+        if let Some(last) = last {
+            if nonce <= *last {
+                return Err(format!("Replayed or out of order nonce {}", nonce));
+            }
+        }
+
+        let mut buf = vec![0u8; sealed.len()];
+        let len = transport.read_message(nonce, sealed, &mut buf).map_err(|e| format!("{:?}", e))?;
+        buf.truncate(len);
+        self.opened[(nonce & 1) as usize] = Some(nonce);
+        Ok(buf)
+    }
+}
+
+/// Reliable packs and position updates travel separately, and can overtake each other
+fn stream(delivery: DeliveryRequirement) -> u64 {
+    match delivery {
+        DeliveryRequirement::UnreliableSequenced(_) => 1,
+        _ => 0,
+    }
+}
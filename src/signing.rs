@@ -0,0 +1,76 @@
+use ed25519_dalek::{PublicKey, SecretKey, Signature, Signer, Verifier};
+use log::info;
+use std::convert::TryFrom;
+use std::io::Write;
+
+// Ed25519 signatures, keys go around as hex so they fit in the config.
+// Separate from the Noise keys in `secure`, those only ever encrypt.
+
+/// Fresh key pair as hex, (secret, public)
+pub fn generate_key() -> Result<(String, String), String> {
+    let secret = hex::encode(rand::random::<[u8; 32]>());
+    let public = public_key(&secret)?;
+    Ok((secret, public))
+}
+
+/// The public half of a hex secret key, as hex
+pub fn public_key(secret: &str) -> Result<String, String> {
+    let secret = secret_key(secret)?;
+    Ok(hex::encode(PublicKey::from(&secret).as_bytes()))
+}
+
+pub fn sign(secret: &str, msg: &[u8]) -> Result<Vec<u8>, String> {
+    let secret = secret_key(secret)?;
+    let keypair = ed25519_dalek::Keypair { public: PublicKey::from(&secret), secret };
+    Ok(keypair.sign(msg).to_bytes().to_vec())
+}
+
+/// Did the owner of `public` sign `msg`. Anything malformed is a no.
+pub fn verify(public: &str, msg: &[u8], signature: &[u8]) -> bool {
+    let public = match hex::decode(public).ok().and_then(|k| PublicKey::from_bytes(&k).ok()) {
+        Some(public) => public,
+        None => return false,
+    };
+    match Signature::try_from(signature) {
+        Ok(signature) => public.verify(msg, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Our secret key from `path`, made and saved there the first time
+pub fn load_or_create(path: &str) -> Result<String, String> {
+    match std::fs::read_to_string(path) {
+        Ok(secret) => Ok(secret.trim().to_string()),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let (secret, public) = generate_key()?;
+            save(path, &secret).map_err(|e| format!("Could not save a key to {}: {}", path, e))?;
+            info!("Made a new key in {}, the server needs to know its public half: {}", path, public);
+            Ok(secret)
+        },
+        Err(e) => Err(format!("Could not read {}: {}", path, e)),
+    }
+}
+
+/// Only we get to read it. `create_new` so we never write through a file
+/// someone else put there in the meantime.
+fn save(path: &str, secret: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(secret.as_bytes())
+}
+
+/// What a player logs in with, their name and the server's challenge, signed
+pub fn proof(name: &str, challenge: &str, secret: &str) -> Result<String, String> {
+    let signature = sign(secret, format!("{} {}", name, challenge).as_bytes())?;
+    Ok(format!("{} {} {}", name, challenge, hex::encode(signature)))
+}
+
+fn secret_key(secret: &str) -> Result<SecretKey, String> {
+    let bytes = hex::decode(secret).map_err(|e| format!("Bad secret key: {}", e))?;
+    SecretKey::from_bytes(&bytes).map_err(|e| format!("Bad secret key: {}", e))
+}
@@ -0,0 +1,16 @@
+use amethyst::prelude::*;
+
+use crate::resources::{AppConfig, LifeformList};
+
+/// Headless client, nothing to draw so there's not much to set up
+pub struct BotState {
+    pub config: AppConfig,
+}
+
+impl SimpleState for BotState {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let world = data.world;
+        world.insert(self.config.clone());
+        world.insert(LifeformList::new());
+    }
+}
@@ -1,5 +1,7 @@
+mod bot_state;
 mod gameplay_state;
 mod server_state;
 
 pub use self::gameplay_state::GamePlayState;
 pub use self::server_state::ServerState;
+pub use self::bot_state::BotState;
@@ -0,0 +1,223 @@
+use amethyst::{
+    core::{SystemDesc, bundle::SystemBundle},
+    ecs::{World, Read, Write, System, SystemData, DispatcherBuilder},
+    shrev::{EventChannel, ReaderId},
+    Result,
+};
+
+use log::{info, warn};
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::{
+    components::{Action, LifeformComponent, Orientation},
+    constants,
+    network::{Cmd, Dest, Pack},
+    resources::{ConnectionState, ConnectionStatus, LifeformList},
+    systems::client::{LifeformEvent, PlayerEvent},
+};
+use super::lifeform::ask_resync;
+
+/// Plays the game with no window. Keeps track of everyone the server tells
+/// it about in a `LifeformList` and either follows a script or wanders about.
+pub struct BotSystem {
+    lf_reader: ReaderId<LifeformEvent>,
+    pl_reader: ReaderId<PlayerEvent>,
+    me: Option<u64>,
+    resyncing: HashMap<u64, Instant>, // Asked the server for these and when
+    script: Vec<Action>, // Played over and over, empty means random
+    step: usize,
+    seq: u32,
+    timer: Instant,
+    report: Instant,
+}
+
+pub struct BotSystemBundle {
+    pub script: Vec<Action>,
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for BotSystemBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<()> {
+        builder.add(
+            BotSystemDesc { script: self.script }.build(world),
+            "bot_system",
+            &["client_tcp_system"],
+        );
+        Ok(())
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct BotSystemDesc {
+    script: Vec<Action>,
+}
+
+impl<'a, 'b> SystemDesc<'a, 'b, BotSystem> for BotSystemDesc {
+    fn build(self, world: &mut World) -> BotSystem {
+        <BotSystem as System<'_>>::SystemData::setup(world);
+        let lf_reader = world
+            .fetch_mut::<EventChannel<LifeformEvent>>()
+            .register_reader();
+        let pl_reader = world
+            .fetch_mut::<EventChannel<PlayerEvent>>()
+            .register_reader();
+        BotSystem::new(lf_reader, pl_reader, self.script)
+    }
+}
+
+impl BotSystem {
+    pub fn new(lf_reader: ReaderId<LifeformEvent>, pl_reader: ReaderId<PlayerEvent>, script: Vec<Action>) -> Self {
+        Self {
+            lf_reader,
+            pl_reader,
+            me: None,
+            resyncing: HashMap::<u64, Instant>::new(),
+            script,
+            step: 0,
+            seq: 0,
+            timer: Instant::now(),
+            report: Instant::now(),
+        }
+    }
+
+    /// What to do next, the script if there is one
+    fn next(&mut self) -> Action {
+        if !self.script.is_empty() {
+            let act = self.script[self.step % self.script.len()].clone();
+            self.step += 1;
+            return act;
+        }
+
+        let mut rng = rand::thread_rng();
+        match rng.gen_range(0, 10) {
+            0 => Action::Melee,
+            1 => Action::Rotate(rng.gen()),
+            _ => Action::Move(rng.gen()),
+        }
+    }
+}
+
+impl<'s> System<'s> for BotSystem {
+    type SystemData = (
+        Read<'s, EventChannel<LifeformEvent>>,
+        Read<'s, EventChannel<PlayerEvent>>,
+        Write<'s, EventChannel<Pack>>,
+        Write<'s, LifeformList>,
+        Read<'s, ConnectionStatus>,
+    );
+
+    fn run(&mut self, (lf_events, pl_events, mut cmd_out, mut world, status): Self::SystemData) {
+        for event in pl_events.read(&mut self.pl_reader) {
+            match event {
+                PlayerEvent::InsertPlayer(lf) => upsert(&mut world, lf.clone()),
+                PlayerEvent::InsertPlayer1(lf) => {
+                    if self.me.is_none() {
+                        info!("Bot is in as {} ({})", lf.name, lf.id());
+                    }
+                    self.me = Some(lf.id());
+                    upsert(&mut world, lf.clone());
+                },
+                PlayerEvent::Ack(ack) => {
+                    // No prediction here, just go with what the server says
+                    if let Some(mut me) = self.me.and_then(|id| world.get_from_id(id)) {
+                        me.x = ack.x;
+                        me.y = ack.y;
+                        me.orientation = ack.orientation.clone();
+                        world.replace(me);
+                    }
+                },
+            }
+        }
+
+        for event in lf_events.read(&mut self.lf_reader) {
+            match event {
+                LifeformEvent::UpdatePlayer(lf) => {
+                    self.resyncing.remove(&lf.id());
+                    upsert(&mut world, lf.clone());
+                },
+                LifeformEvent::UpdateLifeform(delta) => match world.get_from_id(delta.uid) {
+                    Some(mut lf) if delta.follows(&lf) => {
+                        delta.apply(&mut lf);
+                        world.replace(lf);
+                    },
+                    Some(ref lf) if delta.rev <= lf.rev => (), // Old news
+                    _ => if ask_resync(&mut self.resyncing, delta.uid, Instant::now()) {
+                        cmd_out.single_write(Pack::new(Cmd::Resync(delta.uid), Dest::All));
+                    },
+                },
+                LifeformEvent::RemovePlayer(uid) => world.remove_with_id(*uid),
+                LifeformEvent::Swing(_) => (),
+                LifeformEvent::Clear => {
+                    self.resyncing.clear();
+                    *world = LifeformList::new();
+                },
+            }
+        }
+
+        if status.state != ConnectionState::Accepted || self.me.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        if now.duration_since(self.timer).as_millis() >= constants::ACTION_DELAY_MS {
+            self.timer = now;
+            match self.next() {
+                Action::Nothing => (), // Waiting
+                act => {
+                    self.seq += 1;
+                    cmd_out.single_write(Pack::new(Cmd::Input(self.seq, act), Dest::All));
+                },
+            }
+        }
+
+        if now.duration_since(self.report).as_secs() >= 10 {
+            self.report = now;
+            match self.me.and_then(|id| world.get_from_id(id)) {
+                Some(me) => info!(
+                    "Bot at ({}, {}) in {} with {} hp, knows of {} lifeforms, ping {:?}",
+                    me.x, me.y, me.room, me.hp, world.list.iter().filter(|l| l.is_some()).count(), status.ping
+                ),
+                None => warn!("Bot lost track of itself"),
+            }
+        }
+    }
+}
+
+/// Add a lifeform, or swap in the newer copy if we already know it
+fn upsert(world: &mut LifeformList, lf: LifeformComponent) {
+    match world.get_from_id(lf.id()) {
+        Some(_) => world.replace(lf),
+        None => world.add(lf),
+    }
+}
+
+/// Turn a script into actions. One per line, `move <dir>`, `rotate <dir>`,
+/// `melee` or `wait`. Blank lines and lines starting with `#` are skipped.
+pub fn parse_script(text: &str) -> std::result::Result<Vec<Action>, String> {
+    let mut script = Vec::<Action>::new();
+    for (n, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let act = match words.as_slice() {
+            [] => continue,
+            [first, ..] if first.starts_with('#') => continue,
+            ["move", dir] => Action::Move(direction(dir).ok_or(format!("line {}: bad direction {}", n + 1, dir))?),
+            ["rotate", dir] => Action::Rotate(direction(dir).ok_or(format!("line {}: bad direction {}", n + 1, dir))?),
+            ["melee"] => Action::Melee,
+            ["wait"] => Action::Nothing,
+            _ => return Err(format!("line {}: don't know {:?}", n + 1, line)),
+        };
+        script.push(act);
+    }
+    Ok(script)
+}
+
+fn direction(word: &str) -> Option<Orientation> {
+    match word {
+        "n" | "north" => Some(Orientation::North),
+        "s" | "south" => Some(Orientation::South),
+        "e" | "east" => Some(Orientation::East),
+        "w" | "west" => Some(Orientation::West),
+        _ => None,
+    }
+}
@@ -0,0 +1,49 @@
+use amethyst::{
+    core::Transform,
+    ecs::{System, ReadStorage, WriteStorage, Join, Entities},
+};
+
+use crate::{
+    components::{Move, WalkAnimation, Waypoints},
+    constants,
+};
+
+/// Walks remote lifeforms through the steps the server sent, one at a time
+pub struct InterpolateSystem;
+
+impl InterpolateSystem {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<'s> System<'s> for InterpolateSystem {
+    type SystemData = (
+        WriteStorage<'s, Waypoints>,
+        WriteStorage<'s, Move>,
+        WriteStorage<'s, WalkAnimation>,
+        ReadStorage<'s, Transform>,
+        Entities<'s>,
+    );
+
+    fn run(&mut self, (mut waypoints, mut moves, mut walk, transforms, entities): Self::SystemData) {
+        for (e, steps, tr) in (&entities, &mut waypoints, &transforms).join() {
+            // Still walking the last step
+            if let Some(mv) = moves.get(e) {
+                if !mv.delete() {
+                    continue;
+                }
+            }
+
+            if let Some(step) = steps.next(constants::INTERP_DELAY_MS) {
+                // Fallen behind, hurry through what's left
+                let time = (constants::ACTION_DELAY_MS as f32) / 1000.0 / (steps.len() as f32 + 1.0);
+
+                moves.insert(e, Move::new(*tr.translation(), step, time))
+                    .expect("Could not insert move!");
+                walk.insert(e, WalkAnimation::new(time))
+                    .expect("Could not insert walk entity!");
+            }
+        }
+    }
+}
@@ -1,26 +1,36 @@
 use amethyst::{ 
     core::{Transform, SystemDesc, bundle::SystemBundle},
     derive::SystemDesc,
-    ecs::{WriteStorage, Entities, World, Read, System, SystemData, DispatcherBuilder, Join},
+    ecs::{WriteStorage, ReadStorage, Entities, World, Read, Write, System, SystemData, DispatcherBuilder, Join},
     renderer::{SpriteRender, resources::Tint},
     shrev::{EventChannel, ReaderId},
     Result, 
 };
 
 use log::info;
+use nalgebra::distance;
+use std::collections::HashMap;
+use std::time::Instant;
 
 use crate::{ 
-    components::{LifeformComponent},
+    components::{LifeformComponent, LifeformDelta, MeleeAnimation, Move, PlayerOne, Waypoints},
+    constants,
+    network::{Pack, Cmd, Dest},
+    systems::client::PlayerEvent,
 };
 
 pub enum LifeformEvent {
     UpdatePlayer(LifeformComponent),
+    UpdateLifeform(LifeformDelta),
     RemovePlayer(u64),
+    Swing(u64),
+    Clear, // Off to another realm, everyone but us is gone
 }
 
 #[derive(SystemDesc)]
 pub struct LifeformSystem {
     event_reader: ReaderId<LifeformEvent>,
+    resyncing: HashMap<u64, Instant>, // Asked the server for these and when
 }
 
 
@@ -30,7 +40,7 @@ impl<'a, 'b> SystemBundle<'a, 'b> for LifeformSystemBundle {
         builder.add(
             LifeformSystemDesc::default().build(world),
             "lifeform_system",
-            &[],
+            &["client_tcp_system", "player_system"],
         );
         Ok(())
     }
@@ -45,45 +55,106 @@ impl<'a, 'b> SystemDesc<'a, 'b, LifeformSystem> for LifeformSystemDesc {
         let event_reader = world
             .fetch_mut::<EventChannel<LifeformEvent>>()
             .register_reader();
-        LifeformSystem{ event_reader }
+        LifeformSystem{ event_reader, resyncing: HashMap::<u64, Instant>::new() }
+    }
+}
+
+impl LifeformSystem {
+    /// Our copy is missing or out of date, get the whole thing from the server
+    fn resync(&mut self, uid: u64, cmd_out: &mut EventChannel<Pack>) {
+        if ask_resync(&mut self.resyncing, uid, Instant::now()) {
+            info!("Resyncing lifeform {}", uid);
+            cmd_out.single_write(Pack::new(Cmd::Resync(uid), Dest::All));
+        }
+    }
+}
+
+/// Only ask once for each lifeform, unless the server hasn't answered in a
+/// while. It might not be allowed to tell us, or the answer got lost.
+pub fn ask_resync(resyncing: &mut HashMap<u64, Instant>, uid: u64, now: Instant) -> bool {
+    match resyncing.get(&uid) {
+        Some(asked) if now.duration_since(*asked).as_millis() < constants::RESYNC_RETRY_MS => false,
+        _ => {
+            resyncing.insert(uid, now);
+            true
+        },
     }
 }
 
 impl<'s> System<'s> for LifeformSystem {
     type SystemData = (
         Read <'s, EventChannel<LifeformEvent>>,
+        Write<'s, EventChannel<Pack>>,
+        Write<'s, EventChannel<PlayerEvent>>,
         WriteStorage<'s, Transform>,
         WriteStorage<'s, LifeformComponent>,
         WriteStorage<'s, SpriteRender>,
         WriteStorage<'s, Tint>,
+        ReadStorage<'s, PlayerOne>,
+        WriteStorage<'s, Waypoints>,
+        WriteStorage<'s, Move>,
+        WriteStorage<'s, MeleeAnimation>,
         Entities<'s>,
     );
  
-    fn run(&mut self, (events, mut transforms, mut players, mut sprite_renders, mut tints, entities): Self::SystemData) {
+    fn run(&mut self, (events, mut cmd_out, mut pl_events, mut transforms, mut players, mut sprite_renders, mut tints, player_ones, mut waypoints, mut moves, mut swings, entities): Self::SystemData) {
         for event in events.read(&mut self.event_reader) {
             match event{
                 LifeformEvent::UpdatePlayer(new) => {
-                    for (transform, player, sprite_render, tint) in (&mut transforms, &mut players, &mut sprite_renders, &mut tints).join() { 
+                    self.resyncing.remove(&new.id());
+                    let mut found = false;
+                    for (e, transform, player, sprite_render, tint, p1, steps) in (&*entities, &mut transforms, &mut players, &mut sprite_renders, &mut tints, player_ones.maybe(), (&mut waypoints).maybe()).join() { 
                         if player.id() == new.id() {
                             // info!("Updating Player: {:?}", player);
-
-                            if *player.trans().translation() != new.xyz() { 
-                                transform.set_translation(new.xyz()); 
+                            if refresh(transform, player, sprite_render, tint, new, p1.is_some(), steps) {
+                                moves.remove(e);
                             }
+                            found = true;
+                        }
+                    }        
 
-                            if player.orientation != new.orientation {
-                                sprite_render.sprite_number = new.get_dir();
+                    // Never heard of them, must have missed the insert
+                    if !found {
+                        pl_events.single_write(PlayerEvent::InsertPlayer(new.clone()));
+                    }
+                }, 
+                LifeformEvent::UpdateLifeform(delta) => {
+                    let mut stale = true;
+                    for (e, transform, player, sprite_render, tint, p1, steps) in (&*entities, &mut transforms, &mut players, &mut sprite_renders, &mut tints, player_ones.maybe(), (&mut waypoints).maybe()).join() { 
+                        if player.id() == delta.uid {
+                            if delta.follows(player) {
+                                let mut new = player.clone();
+                                delta.apply(&mut new);
+                                if refresh(transform, player, sprite_render, tint, &new, p1.is_some(), steps) {
+                                    moves.remove(e);
+                                }
+                                stale = false;
                             }
-
-                            if player.hp != new.hp {
-                                // oh damn we hurtin
-                                *tint = Tint(new.tint());  
+                            else if delta.rev <= player.rev {
+                                stale = false; // Old news
                             }
-                            
-                            *player = new.clone();
                         }
-                    }        
-                }, 
+                    }
+
+                    if stale {
+                        self.resync(delta.uid, &mut cmd_out);
+                    }
+                },
+                LifeformEvent::Swing(uid) => {
+                    // Player one already swung when the key was pressed
+                    for (e, player, _) in (&*entities, &players, !&player_ones).join() {
+                        if player.id() == *uid && swings.get(e).is_none() {
+                            swings.insert(e, MeleeAnimation::new(player))
+                                .expect("Could not insert swing!");
+                        }
+                    }
+                },
+                LifeformEvent::Clear => {
+                    self.resyncing.clear();
+                    for (e, _, _) in (&*entities, &players, !&player_ones).join() {
+                        entities.delete(e).expect("Failed to delete old player entities");
+                    }
+                },
                 LifeformEvent::RemovePlayer(uid) => {
                     info!("Removing Player of id: {}", uid);
                     for (e, player) in (&*entities, &mut players).join() { 
@@ -96,3 +167,49 @@ impl<'s> System<'s> for LifeformSystem {
         }
     }
 }
+
+/// Bring an entity in line with the newest copy of its lifeform. Player one
+/// keeps its own position, the PlayerSystem sorts that out with the acks.
+/// Everyone else walks there one step at a time, returns true if they had
+/// to be teleported instead.
+fn refresh(
+    transform: &mut Transform,
+    player: &mut LifeformComponent,
+    sprite_render: &mut SpriteRender,
+    tint: &mut Tint,
+    new: &LifeformComponent,
+    p1: bool,
+    steps: Option<&mut Waypoints>,
+) -> bool {
+    let mut teleported = false;
+    let mut new = new.clone();
+    if p1 {
+        new.x = player.x;
+        new.y = player.y;
+        new.orientation = player.orientation.clone();
+    }
+
+    if *player.trans().translation() != new.xyz() { 
+        match steps {
+            Some(steps) if distance(&player.xy(), &new.xy()) <= constants::PLAYER_MOVE => steps.push(new.xyz()),
+            Some(steps) => {
+                steps.clear();
+                transform.set_translation(new.xyz());
+                teleported = true;
+            },
+            None => transform.set_translation(new.xyz()),
+        }
+    }
+
+    if player.orientation != new.orientation || player.skin != new.skin {
+        sprite_render.sprite_number = new.get_dir();
+    }
+
+    if player.hp != new.hp {
+        // oh damn we hurtin
+        *tint = Tint(new.tint());  
+    }
+    
+    *player = new;
+    teleported
+}
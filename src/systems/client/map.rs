@@ -7,20 +7,35 @@ use amethyst::{
     Result, 
 };
 
+use log::{info, warn, error};
 use crate::{
-    map::{Room, TilePosition, Layers},
+    constants,
+    map::{content_hash, Room, TilePosition, Layers},
+    network::{Pack, Cmd, Dest, MapInfo, FileChunk},
     resources::{SpritesContainer},
 };
 
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
 /// Events that pertain to the Auth System
 #[derive(Debug)]
 pub enum MapEvent {
-    TransferMap( String ), 
+    TransferMap( MapInfo ), 
+    Chunk( FileChunk ),
+}
+
+/// A file on its way down from the server
+struct Download {
+    hash: String,
+    chunks: Vec<Option<Vec<u8>>>,
 }
 
 #[derive(SystemDesc)]
 pub struct MapSystem {
     event_reader: ReaderId<MapEvent>,
+    pending: Option<(MapInfo, PathBuf)>, // Map we're waiting on, and where it'll be
+    downloads: HashMap<String, Download>,
 }
 
 
@@ -30,7 +45,7 @@ impl<'a, 'b> SystemBundle<'a, 'b> for MapSystemBundle {
         builder.add(
             MapSystemDesc::default().build(world),
             "map_system",
-            &[],
+            &["client_tcp_system"],
         );
         Ok(())
     }
@@ -45,13 +60,118 @@ impl<'a, 'b> SystemDesc<'a, 'b, MapSystem> for MapSystemDesc {
         let event_reader = world
             .fetch_mut::<EventChannel<MapEvent>>()
             .register_reader();
-        MapSystem{ event_reader }
+        MapSystem{ event_reader, pending: None, downloads: HashMap::<String, Download>::new() }
+    }
+}
+
+impl MapSystem {
+    /// Switch to the map if we have everything, otherwise ask for what's missing
+    fn load(&mut self, info: MapInfo, room: &mut Room, cmd_out: &mut EventChannel<Pack>) {
+        // Our own copy is fine if it's the same as the server's
+        if matches(&info.name, &info.hash) && matches(&info.tileset, &info.tileset_hash) {
+            self.pending = None;
+            change(room, &info.name);
+            return;
+        }
+
+        let dir = match cache_dir(&info) {
+            Some(dir) => dir,
+            None => {
+                error!("Server sent a map path we won't write to: {:?}", info);
+                return;
+            },
+        };
+        let map = dir.join(&info.name);
+        let tileset = dir.join(&info.tileset);
+
+        let mut missing = false;
+        for (name, hash, path) in [(&info.name, &info.hash, &map), (&info.tileset, &info.tileset_hash, &tileset)].iter() {
+            if !matches(path, hash) {
+                missing = true;
+                if !self.downloads.contains_key(*name) {
+                    info!("Downloading {} from the server", name);
+                    cmd_out.single_write(Pack::new(Cmd::FetchFile(name.to_string()), Dest::All));
+                    self.downloads.insert(name.to_string(), Download { hash: hash.to_string(), chunks: Vec::new() });
+                }
+            }
+        }
+
+        if missing {
+            self.pending = Some((info, dir));
+        } else {
+            self.pending = None;
+            change(room, &map);
+        }
+    }
+
+    /// Piece of a download, writes it to the cache once it's all there
+    fn chunk(&mut self, chunk: &FileChunk) {
+        let (dir, download) = match (&self.pending, self.downloads.get_mut(&chunk.name)) {
+            (Some((_, dir)), Some(download)) if download.hash == chunk.hash => (dir, download),
+            _ => {
+                warn!("Got a piece of {} we didn't ask for", chunk.name);
+                return;
+            },
+        };
+        if download.chunks.is_empty() {
+            download.chunks = vec![None; chunk.total as usize];
+        }
+        match download.chunks.get_mut(chunk.index as usize) {
+            Some(slot) if chunk.total as usize == download.chunks.len() => *slot = Some(chunk.data.clone()),
+            _ => {
+                warn!("Piece {}/{} of {} doesn't fit", chunk.index, chunk.total, chunk.name);
+                return;
+            },
+        }
+        if download.chunks.iter().any(|c| c.is_none()) {
+            return;
+        }
+
+        let bytes: Vec<u8> = download.chunks.drain(..).flatten().flatten().collect();
+        let download = self.downloads.remove(&chunk.name).unwrap();
+        if content_hash(&bytes) != download.hash {
+            error!("{} came down corrupted, not saving it", chunk.name);
+            return;
+        }
+        let path = dir.join(&chunk.name);
+        let written = path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, &bytes));
+        match written {
+            Ok(_) => info!("Saved {} to {}", chunk.name, path.display()),
+            Err(e) => error!("Could not save {} to {}: {}", chunk.name, path.display(), e),
+        }
+    }
+}
+
+/// Is the file there and the same as the server's
+fn matches<P: AsRef<Path>>(path: P, hash: &str) -> bool {
+    std::fs::read(path).map_or(false, |bytes| content_hash(&bytes) == hash)
+}
+
+/// Where downloads for this map and tileset go. Files keep the server's paths
+/// under it so the map still finds its tileset. Won't go outside the cache.
+fn cache_dir(info: &MapInfo) -> Option<PathBuf> {
+    let safe = |name: &String| Path::new(name).components().all(|c| match c {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    });
+    if !safe(&info.name) || !safe(&info.tileset) || info.hash.len() < 16 || info.tileset_hash.len() < 16 {
+        return None;
+    }
+    Some(Path::new(constants::MAP_CACHE_DIR).join(format!("{}-{}", &info.hash[..16], &info.tileset_hash[..16])))
+}
+
+fn change(room: &mut Room, path: &Path) {
+    if let Err(e) = room.change(path.to_string_lossy().to_string()) {
+        error!("Could not load map {}", e);
     }
 }
 
 impl<'s> System<'s> for MapSystem{
     type SystemData = (
         Read <'s, EventChannel<MapEvent>>,
+        Write<'s, EventChannel<Pack>>,
         WriteStorage<'s, Transform>,
         WriteStorage<'s, SpriteRender>,
         WriteStorage<'s, TilePosition>,
@@ -62,10 +182,19 @@ impl<'s> System<'s> for MapSystem{
     
     /// Should ONLY be called in a re-draw event of the map
     /// Resource room should be updated with the newest room
-    fn run(&mut self, (ev, mut transforms, mut sprite_renders, mut tiles_pos, mut room, container, entities): Self::SystemData) {
+    fn run(&mut self, (ev, mut cmd_out, mut transforms, mut sprite_renders, mut tiles_pos, mut room, container, entities): Self::SystemData) {
         for event in ev.read(&mut self.event_reader) {
             match event{
-                MapEvent::TransferMap(name) => room.change(name.to_string()),
+                MapEvent::TransferMap(info) => self.load(info.clone(), &mut room, &mut cmd_out),
+                MapEvent::Chunk(chunk) => {
+                    self.chunk(chunk);
+                    // Might have been the last piece
+                    if self.downloads.is_empty() {
+                        if let Some((info, _)) = self.pending.take() {
+                            self.load(info, &mut room, &mut cmd_out);
+                        }
+                    }
+                },
             }
         }
         
@@ -74,6 +203,7 @@ impl<'s> System<'s> for MapSystem{
             for entity in room.tile_ent.iter() {
                 entities.delete(*entity).expect("Failed to delete old map entities");
             }
+            room.tile_ent.clear();
             
             // Add new tiles
             let mut ent_list: Vec<Entity> = Vec::new();
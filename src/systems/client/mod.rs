@@ -13,6 +13,13 @@ pub use self::map::MapEvent;
 mod network;
 pub use self::network::TcpSystemBundle;
 
+mod spectator;
+pub use self::spectator::SpectatorSystemBundle;
+
+mod bot;
+pub use self::bot::BotSystemBundle;
+pub use self::bot::parse_script;
+
 mod wallet;
 pub use self::wallet::WalletSystemBundle;
 
@@ -25,6 +32,9 @@ pub use self::melee::MeleeAnimationSystem;
 mod movement;
 pub use self::movement::MoveSystem;
 
+mod interpolate;
+pub use self::interpolate::InterpolateSystem;
+
 mod input;
 pub use self::input::InputSystem;
 pub use self::input::InputSystemBundle;
@@ -29,8 +29,11 @@ impl<'s> System<'s> for MoveSystem {
     );
 
     fn run(&mut self, (mut moves, mut transforms, entities, time): Self::SystemData) {
-        for item in self.delete_list.pop() {
-            moves.remove(item);
+        for item in self.delete_list.drain(..) {
+            // Someone may have started a new move since
+            if moves.get(item).map_or(false, |mv| mv.delete()) {
+                moves.remove(item);
+            }
         }
         
         for (e, move_, tr) in (&entities, &mut moves, &mut transforms).join() {
@@ -3,13 +3,20 @@ use amethyst::{
     core::{SystemDesc},
     ecs::{Read, System, SystemData, World, Write, DispatcherBuilder},
     shrev::{EventChannel, ReaderId}, 
-    network::simulation::{NetworkSimulationEvent, NetworkSimulationTime, TransportResource}, 
+    network::simulation::{DeliveryRequirement, NetworkSimulationEvent, NetworkSimulationTime, TransportResource, UrgencyRequirement, tcp::TcpNetworkResource},
     Result, 
 };
 use log::{info, error};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Instant;
 
-use crate::network::{Pack, Cmd, Dest};
-use crate::resources::{AppConfig};
+use crate::accounts;
+use crate::capture::{self, Entry, Recorder};
+use crate::constants;
+use crate::network::{Pack, Cmd, Dest, Hello, ConnectReply, Batcher, Reassembly, PROTOCOL_VERSION};
+use crate::resources::{AppConfig, ConnectionStatus, ConnectionState, NetStats};
+use crate::secure::SecureChannel;
 use crate::systems::client::{LifeformEvent, PlayerEvent, MapEvent};
 
 pub struct TcpSystemBundle;
@@ -46,6 +53,15 @@ pub struct TcpSystem {
     net_reader: ReaderId<NetworkSimulationEvent>,
     packs_reader: ReaderId<Pack>,
     connected: bool,
+    inbound: VecDeque<Pack>,
+    partial: Reassembly, // Packs cut off at the end of a read
+    ping_timer: Instant,
+    pinged: Option<(u64, Instant)>, // Ping we're waiting on
+    nonce: u64,
+    secure: Option<SecureChannel>, // Only when the config asks for encryption
+    server: Option<SocketAddr>,    // Where another realm sent us, instead of the config's
+    greeted: Option<Instant>,      // When we asked the server for a challenge
+    challenge: Option<String>,     // What our proof has to sign
 }
 
 impl TcpSystem {
@@ -54,6 +70,50 @@ impl TcpSystem {
             net_reader,
             packs_reader,
             connected: false,
+            inbound: VecDeque::<Pack>::new(),
+            partial: Reassembly::default(),
+            ping_timer: Instant::now(),
+            pinged: None,
+            nonce: 0,
+            secure: None,
+            server: None,
+            greeted: None,
+            challenge: None,
+        }
+    }
+
+    /// Take the encryption off whatever the server sent, the answer to our
+    /// handshake gets dealt with here too
+    fn unseal(&mut self, pack: Pack, status: &mut ConnectionStatus) -> Vec<Pack> {
+        match (pack.cmd, self.secure.as_mut()) {
+            (Cmd::Handshake(msg), Some(channel)) => {
+                match channel.read_handshake(&msg) {
+                    Ok(()) => info!("Handshake done, the connection is encrypted"),
+                    Err(e) => {
+                        error!("Handshake failed: {}", e);
+                        status.state = ConnectionState::Rejected(e);
+                        self.connected = true; // Don't say hello to someone we don't trust
+                    },
+                }
+                Vec::<Pack>::new()
+            },
+            (Cmd::Sealed(nonce, sealed), Some(channel)) => match channel.open(nonce, &sealed) {
+                Ok(plain) => Pack::from_bin_many(plain)
+                    .into_iter()
+                    .filter_map(|p| p.map_err(|e| error!("Malformed pack from server: {:?}", e)).ok())
+                    .collect(),
+                Err(e) => {
+                    error!("Could not open pack from server: {}", e);
+                    Vec::<Pack>::new()
+                },
+            },
+            // The server can only turn us down in the clear, it doesn't know we're encrypting yet
+            (cmd @ Cmd::ConnectReply(ConnectReply::Reject(_)), _) => vec![Pack::new(cmd, pack.dest)],
+            (_, Some(_)) => {
+                error!("Plain pack from the server on an encrypted connection");
+                Vec::<Pack>::new()
+            },
+            (cmd, None) => vec![Pack::new(cmd, pack.dest)],
         }
     }
 }
@@ -68,38 +128,189 @@ impl<'a> System<'a> for TcpSystem {
         Write<'a, TransportResource>,
         Read<'a, EventChannel<NetworkSimulationEvent>>,
         Read<'a, AppConfig>,
+        Write<'a, ConnectionStatus>,
+        Write<'a, NetStats>,
+        Option<Write<'a, Recorder>>, // Only there when capturing
+        Option<Write<'a, TcpNetworkResource>>, // Only there when running over TCP
     );
-    fn run(&mut self, (in_packs, mut lf_events, mut pl_events, mut map_events, sim_time, mut net, channel, conf): Self::SystemData) {
+    fn run(&mut self, (in_packs, mut lf_events, mut pl_events, mut map_events, sim_time, mut net, channel, conf, mut status, mut stats, mut capture, mut tcp): Self::SystemData) {
+        let now = Instant::now();
+        let server: SocketAddr = self.server.unwrap_or_else(|| conf.server_ip.parse().unwrap());
+        let mut record = |entry: Entry| {
+            if let Some(capture) = capture.as_mut() {
+                capture.record(server, entry);
+            }
+        };
+
+        // Keep the server from timing us out, and see how far away it is
+        if status.state == ConnectionState::Accepted && now.duration_since(self.ping_timer).as_millis() >= constants::PING_INTERVAL_MS {
+            self.ping_timer = now;
+            self.nonce += 1;
+            self.pinged = Some((self.nonce, now));
+            let p = Pack::new(Cmd::Ping(self.nonce), Dest::All);
+            record(Entry::Out(p.clone()));
+            match p.to_bin() {
+                Ok(bin) => send(&mut net, server, &bin, p.cmd.delivery(), self.secure.as_mut()),
+                Err(e) => error!("Could not serialize ping: {:?}", e),
+            }
+        }
+
         if sim_time.should_send_message_now() {
-            if !self.connected {
-                info!("We are not connected, ready player 1");
-                let proof = format!("{} 1580235330 SignatureHere", conf.player_name);
-                let p = Pack::new(Cmd::Connect(proof.to_string()), Dest::All);  
-                net.send(conf.server_ip.parse().unwrap(), &p.to_bin());
-                self.connected = true;
+            if !self.connected && conf.encrypt && self.secure.is_none() {
+                // Agree on keys before anything worth reading goes over
+                info!("Starting the handshake");
+                match SecureChannel::initiator(&conf.server_key) {
+                    Ok((channel, p)) => {
+                        self.secure = Some(channel);
+                        record(Entry::Handshake);
+                        match p.to_bin() {
+                            Ok(bin) => send(&mut net, server, &bin, p.cmd.delivery(), None),
+                            Err(e) => error!("Could not serialize handshake: {:?}", e),
+                        }
+                    },
+                    Err(e) => {
+                        error!("Could not start the handshake: {}", e);
+                        status.state = ConnectionState::Rejected(e);
+                        self.connected = true;
+                    },
+                }
             }
-            else {
+            else if !self.connected && self.secure.as_ref().map_or(true, |c| c.is_ready()) {
+                match self.challenge.take() {
+                    None if self.greeted.is_none() => {
+                        // Can't prove who we are until the server gives us something to sign
+                        let p = Pack::new(Cmd::Greet(PROTOCOL_VERSION), Dest::All);
+                        record(Entry::Out(p.clone()));
+                        match p.to_bin() {
+                            Ok(bin) => send(&mut net, server, &bin, p.cmd.delivery(), self.secure.as_mut()),
+                            Err(e) => error!("Could not serialize greet: {:?}", e),
+                        }
+                        self.greeted = Some(now);
+                    },
+                    // A server from before challenges can't read a Greet, so it never answers
+                    None if self.greeted.map_or(false, |at| now.duration_since(at).as_millis() >= constants::CHALLENGE_WAIT_MS) => {
+                        if status.state == ConnectionState::Connecting {
+                            let reason = format!(
+                                "No challenge from the server after {}ms, it might be older than us (protocol {})",
+                                constants::CHALLENGE_WAIT_MS, PROTOCOL_VERSION,
+                            );
+                            error!("{}", reason);
+                            status.state = ConnectionState::Rejected(reason);
+                        }
+                        self.connected = true;
+                    },
+                    None => (), // Still waiting on the challenge
+                    Some(challenge) => {
+                        info!("We are not connected, ready player 1");
+                        let proof = match (conf.password.as_str(), conf.encrypt) {
+                            ("", _) | (_, true) => accounts::proof(&conf, &conf.player_name, &challenge),
+                            _ => Err("Not sending a password without encrypt on".to_string()),
+                        };
+                        match proof {
+                            Ok(proof) => {
+                                let mut hello = Hello::new(proof);
+                                hello.resume = status.resume.clone();
+                                hello.ticket = status.ticket.clone();
+                                hello.register = conf.register;
+                                if !conf.spectate.is_empty() {
+                                    // Back to whatever we were watching if we got dropped
+                                    let room = status.watching.get_or_insert_with(|| conf.spectate.clone());
+                                    hello.spectate = Some(room.clone());
+                                }
+                                let p = Pack::new(Cmd::Connect(hello), Dest::All);
+                                record(Entry::Out(match conf.password.as_str() {
+                                    "" => p.clone(),
+                                    _ => capture::redact(p.clone()),
+                                }));
+                                match p.to_bin() {
+                                    Ok(bin) => send(&mut net, server, &bin, p.cmd.delivery(), self.secure.as_mut()),
+                                    Err(e) => error!("Could not serialize connect: {:?}", e),
+                                }
+                            },
+                            Err(e) => {
+                                error!("Could not sign in: {}", e);
+                                status.state = ConnectionState::Rejected(e);
+                            },
+                        }
+                        self.connected = true;
+                    },
+                }
+            }
+            else if self.connected {
+                // Everything this frame goes over together
+                let mut batcher = Batcher::new();
                 for pack in in_packs.read(&mut self.packs_reader) {
-                    net.send(conf.server_ip.parse().unwrap(), &pack.to_bin());
+                    record(Entry::Out(pack.clone()));
+                    match pack.to_bin() {
+                        Ok(bin) => batcher.push(server, &pack, &bin),
+                        Err(e) => error!("Could not serialize pack {:?}: {:?}", pack, e),
+                    }
+                }
+                for (_, batch) in batcher.drain() {
+                    send(&mut net, server, &batch.bin, batch.delivery, self.secure.as_mut());
                 }
             }
         }
 
-        // Incoming packets
-        let mut packs = Vec::<Pack>::new();
+        // Incoming packets, queued in the order they arrived
         for event in channel.read(&mut self.net_reader) {
             match event {
-                NetworkSimulationEvent::Message(_addr, payload) => {
+                NetworkSimulationEvent::Message(addr, payload) => {
                     // info!("Payload: {:?}", payload);
                     if *payload != b"ok".to_vec() {
-                        let pl =  Pack::from_bin(payload.to_vec());
-                        // info!("Payload: {:?}", pl);
-                        packs.push(pl);
+                        for pack in self.partial.feed(*addr, payload) {
+                            match pack {
+                                // Ok(pl) => info!("Payload: {:?}", pl),
+                                Ok(pl) => for pl in self.unseal(pl, &mut status) {
+                                    stats.received += 1;
+                                    record(Entry::In(pl.clone()));
+                                    match pl.cmd {
+                                        // Answer straight away, sitting in the queue would skew their RTT
+                                        Cmd::Ping(n) => {
+                                            let p = Pack::new(Cmd::Pong(n), Dest::All);
+                                            record(Entry::Out(p.clone()));
+                                            match p.to_bin() {
+                                                Ok(bin) => send(&mut net, server, &bin, p.cmd.delivery(), self.secure.as_mut()),
+                                                Err(e) => error!("Could not serialize pong: {:?}", e),
+                                            }
+                                        },
+                                        Cmd::Pong(n) => {
+                                            if let Some((nonce, sent)) = self.pinged {
+                                                if nonce == n {
+                                                    status.ping = Some(now.duration_since(sent));
+                                                    self.pinged = None;
+                                                }
+                                            }
+                                        },
+                                        _ => self.inbound.push_back(pl),
+                                    }
+                                },
+                                Err(e) => error!("Malformed pack from server: {:?}", e),
+                            }
+                        }
                     }
                 }
-                NetworkSimulationEvent::Connect(addr) => info!("New client connection: {}", addr),
+                NetworkSimulationEvent::Connect(addr) => {
+                    info!("New client connection: {}", addr);
+                    record(Entry::Connect);
+                },
+                NetworkSimulationEvent::Disconnect(addr) if *addr != self.server.unwrap_or(server) => {
+                    info!("Realm we left hung up: {}", addr);
+                },
+                NetworkSimulationEvent::Disconnect(addr) if matches!(status.state, ConnectionState::Rejected(_)) => {
+                    info!("Server hung up on us: {}", addr); // No point saying hello again
+                },
                 NetworkSimulationEvent::Disconnect(addr) => {
                     info!("Server Disconnected: {}", addr);
+                    record(Entry::Disconnect);
+                    self.partial.forget(addr);
+                    // Say hello again, with the session token if we got one
+                    self.connected = false;
+                    status.state = ConnectionState::Connecting;
+                    self.pinged = None;
+                    self.secure = None; // New connection, new keys
+                    self.greeted = None;
+                    self.challenge = None;
                 }
                 NetworkSimulationEvent::RecvError(e) => {
                     error!("Recv Error: {:?}", e);
@@ -111,15 +322,91 @@ impl<'a> System<'a> for TcpSystem {
             }
         }
         
-        for pack in packs.pop() {
+        // Only handle so many packs a frame, the rest wait their turn
+        let budget = match conf.inbound_budget {
+            0 => self.inbound.len(),
+            n => n.min(self.inbound.len()),
+        };
+        stats.dispatched += budget as u64;
+
+        for pack in self.inbound.drain(..budget) {
             match pack.cmd {
-                Cmd::UpdatePlayer(pl) => lf_events.single_write(LifeformEvent::UpdatePlayer(pl)),
+                Cmd::UpdatePlayer(pl) | Cmd::Resynced(pl) => lf_events.single_write(LifeformEvent::UpdatePlayer(pl)),
+                Cmd::UpdateLifeform(delta) => lf_events.single_write(LifeformEvent::UpdateLifeform(delta)),
+                Cmd::Swing(uid) => lf_events.single_write(LifeformEvent::Swing(uid)),
                 Cmd::RemovePlayer(u64) => lf_events.single_write(LifeformEvent::RemovePlayer(u64)),
                 Cmd::InsertPlayer(pl) => pl_events.single_write(PlayerEvent::InsertPlayer(pl)),
                 Cmd::InsertPlayer1(pl) => pl_events.single_write(PlayerEvent::InsertPlayer1(pl)),
+                Cmd::Ack(ack) => pl_events.single_write(PlayerEvent::Ack(ack)),
                 Cmd::TransferMap(map) => map_events.single_write(MapEvent::TransferMap(map)),
+                Cmd::FileChunk(chunk) => map_events.single_write(MapEvent::Chunk(chunk)),
+                Cmd::Challenge(challenge) => self.challenge = Some(challenge),
+                Cmd::Session(token) => status.resume = Some(token),
+                Cmd::Rooms(rooms) => status.rooms = rooms,
+                Cmd::Transfer(realm, ticket) => match realm.parse::<SocketAddr>() {
+                    Ok(addr) => {
+                        info!("Server is sending us over to {}", realm);
+                        if let Some(tcp) = tcp.as_mut() {
+                            tcp.drop_stream(server);
+                        }
+                        self.partial.forget(&server);
+                        // Start over with the new server, the ticket gets us our lifeform back
+                        self.server = Some(addr);
+                        self.connected = false;
+                        self.pinged = None;
+                        self.secure = None;
+                        self.greeted = None;
+                        self.challenge = None;
+                        status.state = ConnectionState::Connecting;
+                        status.resume = None;
+                        status.ticket = Some(ticket);
+                        lf_events.single_write(LifeformEvent::Clear);
+                    },
+                    Err(e) => error!("Server sent us to {} which isn't an address: {}", realm, e),
+                },
+                Cmd::ConnectReply(ConnectReply::Accept(version, capabilities)) => {
+                    info!("Server accepted us, protocol {} with {:?}", version, capabilities);
+                    status.state = ConnectionState::Accepted;
+                    status.ticket = None;
+                    status.server_version = Some(version);
+                    status.capabilities = capabilities;
+                },
+                Cmd::ConnectReply(ConnectReply::Reject(reason)) => {
+                    error!("Server rejected us: {}", reason);
+                    status.state = ConnectionState::Rejected(reason);
+                },
+                Cmd::Kicked(reason) => {
+                    error!("Server kicked us: {}", reason);
+                    status.state = ConnectionState::Rejected(reason);
+                },
+                Cmd::Message(text) => info!("Server says: {}", text),
                 _ => ()
             }
         }
+
+        stats.queued(self.inbound.len());
     }
 }
+
+/// Send to the server, sealed if the handshake is done
+fn send(
+    net: &mut TransportResource,
+    server: SocketAddr,
+    bin: &[u8],
+    delivery: DeliveryRequirement,
+    channel: Option<&mut SecureChannel>,
+) {
+    let bin = match channel {
+        Some(channel) if channel.is_ready() => {
+            match channel.seal(bin, delivery).and_then(|p| p.to_bin().map_err(|e| format!("{:?}", e))) {
+                Ok(sealed) => sealed,
+                Err(e) => {
+                    error!("Could not seal pack for the server: {}", e);
+                    return;
+                },
+            }
+        },
+        _ => bin.to_vec(),
+    };
+    net.send_with_requirements(server, &bin, delivery, UrgencyRequirement::OnTick);
+}
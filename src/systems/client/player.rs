@@ -9,20 +9,22 @@ use amethyst::{
 };
 
 use log::info;
+use std::collections::VecDeque;
 use std::time::Instant;
 
 use crate::{
-    components::{Action, LifeformComponent, MeleeAnimation, Move, WalkAnimation},
+    components::{Action, LifeformComponent, MeleeAnimation, Move, PlayerOne, WalkAnimation, Waypoints},
     constants,
     map::Room,
     mech::get_letter,
-    network::{Cmd, Dest, Pack},
-    resources::{Command, CommandQueue, SpritesContainer},
+    network::{Ack, Cmd, Dest, Pack},
+    resources::{AppConfig, Command, CommandQueue, SpritesContainer},
 };
 
 pub enum PlayerEvent {
     InsertPlayer(LifeformComponent),
     InsertPlayer1(LifeformComponent),
+    Ack(Ack),
 }
 
 #[derive(SystemDesc)]
@@ -30,6 +32,8 @@ pub struct PlayerSystem {
     p1: Option<Entity>,
     timer: Option<Instant>,
     event_reader: ReaderId<PlayerEvent>,
+    seq: u32,
+    pending: VecDeque<(u32, Action)>, // Inputs the server hasn't acked yet
 }
 
 
@@ -39,7 +43,7 @@ impl<'a, 'b> SystemBundle<'a, 'b> for PlayerSystemBundle {
         builder.add(
             PlayerSystemDesc::default().build(world),
             "player_system",
-            &[],
+            &["client_tcp_system"],
         );
         Ok(())
     }
@@ -64,8 +68,31 @@ impl PlayerSystem {
             p1: None,
             timer: None,
             event_reader,
+            seq: 0,
+            pending: VecDeque::<(u32, Action)>::new(),
         }
     }
+
+    /// Number an action and hang on to it until the server acks it
+    fn input(&mut self, act: Action) -> Pack {
+        self.seq += 1;
+        self.pending.push_back((self.seq, act.clone()));
+        Pack::new(Cmd::Input(self.seq, act), Dest::All)
+    }
+}
+
+/// Play an action the way the server will, without knowing where anyone else is
+fn predict(player: &mut LifeformComponent, act: &Action, room: &Room) {
+    match act {
+        Action::Rotate(dir) => player.orientation = dir.clone(),
+        Action::Move(dir) => {
+            player.orientation = dir.clone();
+            if room.allowed_move(&player.trans(), &player.orientation) {
+                player.walk();
+            }
+        }
+        _ => (),
+    }
 }
 
 impl<'s> System<'s> for PlayerSystem {
@@ -80,10 +107,13 @@ impl<'s> System<'s> for PlayerSystem {
         WriteStorage<'s, Parent>,
         WriteStorage<'s, SpriteRender>,
         WriteStorage<'s, Tint>,
+        WriteStorage<'s, PlayerOne>,
+        WriteStorage<'s, Waypoints>,
         Write<'s, Room>,
         Entities<'s>,
         Write<'s, CommandQueue>,
         Read<'s, SpritesContainer>,
+        Read<'s, AppConfig>,
     );
 
     fn run(
@@ -99,15 +129,22 @@ impl<'s> System<'s> for PlayerSystem {
             mut parents,
             mut sprite_renders,
             mut tints,
+            mut player_ones,
+            mut waypoints,
             room,
             entities,
             mut command_queue,
             s,
+            conf,
         ): Self::SystemData,
     ) {
         for event in events.read(&mut self.event_reader) {
             match &event {
                 PlayerEvent::InsertPlayer(play) => {
+                    // Already have them, the server sends everything again when we resume
+                    if (&players).join().any(|p| p.id() == play.id()) {
+                        continue;
+                    }
                     let e = Some(
                         entities
                             .build_entity()
@@ -115,6 +152,7 @@ impl<'s> System<'s> for PlayerSystem {
                             .with(play.get_orientated(&s.sprites), &mut sprite_renders)
                             .with(Tint(play.tint()), &mut tints)
                             .with(play.clone(), &mut players)
+                            .with(Waypoints::new(), &mut waypoints)
                             .build(),
                     );
                     // Write the players name
@@ -131,6 +169,16 @@ impl<'s> System<'s> for PlayerSystem {
                     }
                 }
                 PlayerEvent::InsertPlayer1(play) => {
+                    // Resumed after a drop or moved by the server, put us where it says
+                    if let Some(p1) = self.p1 {
+                        self.pending.clear();
+                        moves.remove(p1);
+                        walk.remove(p1);
+                        transforms.insert(p1, play.trans()).expect("Could not move player 1!");
+                        sprite_renders.get_mut(p1).unwrap().sprite_number = play.get_dir();
+                        players.insert(p1, play.clone()).expect("Could not update player 1!");
+                        continue;
+                    }
                     let e = Some(
                         entities
                             .build_entity()
@@ -155,12 +203,54 @@ impl<'s> System<'s> for PlayerSystem {
 
                     if self.p1.is_none() {
                         info!("Inserting Player 1");
+                        player_ones.insert(e.unwrap(), PlayerOne).expect("Could not mark player 1!");
                         self.p1 = e;
                         self.timer = Some(Instant::now());
                     }
                 }
+                PlayerEvent::Ack(ack) => {
+                    if let Some(p1) = self.p1 {
+                        self.pending.retain(|(seq, _)| *seq > ack.seq);
+
+                        // Start where the server says we are and replay what it hasn't seen yet
+                        let player = players.get_mut(p1).unwrap();
+                        let mut predicted = player.clone();
+                        predicted.x = ack.x;
+                        predicted.y = ack.y;
+                        predicted.orientation = ack.orientation.clone();
+                        for (_, act) in self.pending.iter() {
+                            predict(&mut predicted, act, &room);
+                        }
+
+                        if predicted.xy() != player.xy() {
+                            info!("Server put us at ({}, {}), correcting", predicted.x, predicted.y);
+                            let tr = transforms.get_mut(p1).unwrap();
+                            let mv = Move::new(
+                                *tr.translation(),
+                                predicted.xyz(),
+                                (constants::ACTION_DELAY_MS as f32) / 1000.0,
+                            );
+                            moves.insert(p1, mv).expect("Cannot insert player");
+                        }
+
+                        if predicted.orientation != player.orientation {
+                            let spr = sprite_renders.get_mut(p1).unwrap();
+                            spr.sprite_number = predicted.get_dir();
+                        }
+
+                        player.x = predicted.x;
+                        player.y = predicted.y;
+                        player.orientation = predicted.orientation;
+                    }
+                }
             }
         }
+        // Spectators only watch, throw away anything they pressed
+        if !conf.spectate.is_empty() {
+            while command_queue.get().is_some() {}
+            return;
+        }
+
         if self.p1.is_some() {
             let now = Instant::now();
             let p1 = self.p1.unwrap();
@@ -177,17 +267,17 @@ impl<'s> System<'s> for PlayerSystem {
                                 if player.update_orientation(dir) {
                                     // Update self
                                     spr.sprite_number = player.get_dir(); // Change sprite
-                                    cmd_out.single_write(Pack::new(
-                                        Cmd::Action(Action::Rotate(player.orientation.clone())),
-                                        Dest::All,
-                                    ));
+                                    cmd_out.single_write(
+                                        self.input(Action::Rotate(player.orientation.clone())),
+                                    );
                                 }
                                 player.in_front() // Get transform of in front
                             };
 
                             let mut adj_player: Option<LifeformComponent> = None;
-                            for (transform, p) in (&mut transforms, &mut players).join() {
-                                if *transform.translation() == *adj_player_tr.translation() {
+                            for p in (&players).join() {
+                                // Where they really are, not where they're drawn
+                                if *p.trans().translation() == *adj_player_tr.translation() {
                                     // There's someone in the way!
                                     adj_player = Some(p.clone());
                                 }
@@ -214,17 +304,16 @@ impl<'s> System<'s> for PlayerSystem {
                                 ).expect("Could not insert walk entity!");
                                 moves.insert(p1, mv).expect("Cannot insert player");
 
-                                cmd_out.single_write(Pack::new(
-                                    Cmd::Action(Action::Move(player.orientation.clone())),
-                                    Dest::All,
-                                ));
+                                cmd_out.single_write(
+                                    self.input(Action::Move(player.orientation.clone())),
+                                );
                             }
                         }
                         Command::Melee => {
                             info!("Punch");
                             swing.insert(p1, MeleeAnimation::new(players.get_mut(p1).unwrap()))
                                 .expect("Could not insert player!");
-                            cmd_out.single_write(Pack::new(Cmd::Action(Action::Melee), Dest::All));
+                            cmd_out.single_write(self.input(Action::Melee));
                         }
                         _ => {}
                     }
@@ -0,0 +1,88 @@
+use amethyst::{
+    core::{Transform, SystemDesc, bundle::SystemBundle, timing::Time},
+    ecs::{World, Join, Read, ReadStorage, Write, WriteStorage, System, SystemData, DispatcherBuilder},
+    input::{InputHandler, VirtualKeyCode},
+    renderer::Camera,
+    shrev::EventChannel,
+    Result,
+};
+
+use log::info;
+
+use crate::{
+    constants,
+    key_bindings::MovementBindingTypes,
+    network::{Cmd, Dest, Pack},
+    resources::{ConnectionState, ConnectionStatus},
+    systems::client::LifeformEvent,
+};
+
+/// Lets a spectator look around. Arrow keys pan the camera, tab moves on
+/// to the next room the server has.
+pub struct SpectatorSystem {
+    cycling: bool, // Tab was down last frame
+}
+
+pub struct SpectatorSystemBundle;
+impl<'a, 'b> SystemBundle<'a, 'b> for SpectatorSystemBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<()> {
+        builder.add(
+            SpectatorSystemDesc::default().build(world),
+            "spectator_system",
+            &["client_tcp_system"],
+        );
+        Ok(())
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SpectatorSystemDesc;
+
+impl<'a, 'b> SystemDesc<'a, 'b, SpectatorSystem> for SpectatorSystemDesc {
+    fn build(self, world: &mut World) -> SpectatorSystem {
+        <SpectatorSystem as System<'_>>::SystemData::setup(world);
+        SpectatorSystem { cycling: false }
+    }
+}
+
+impl<'s> System<'s> for SpectatorSystem {
+    type SystemData = (
+        Read<'s, InputHandler<MovementBindingTypes>>,
+        Read<'s, Time>,
+        ReadStorage<'s, Camera>,
+        WriteStorage<'s, Transform>,
+        Write<'s, EventChannel<Pack>>,
+        Write<'s, EventChannel<LifeformEvent>>,
+        Write<'s, ConnectionStatus>,
+    );
+
+    fn run(&mut self, (input, time, cameras, mut transforms, mut cmd_out, mut lf_events, mut status): Self::SystemData) {
+        let step = constants::SPECTATOR_PAN_SPEED * time.delta_seconds();
+        let (mut dx, mut dy) = (0.0, 0.0);
+        if input.key_is_down(VirtualKeyCode::Left) { dx -= step; }
+        if input.key_is_down(VirtualKeyCode::Right) { dx += step; }
+        if input.key_is_down(VirtualKeyCode::Down) { dy -= step; }
+        if input.key_is_down(VirtualKeyCode::Up) { dy += step; }
+        for (_, transform) in (&cameras, &mut transforms).join() {
+            transform.prepend_translation_x(dx);
+            transform.prepend_translation_y(dy);
+        }
+
+        // Only once per press
+        let tab = input.key_is_down(VirtualKeyCode::Tab);
+        let pressed = tab && !self.cycling;
+        self.cycling = tab;
+        if !pressed || status.state != ConnectionState::Accepted || status.rooms.is_empty() {
+            return;
+        }
+
+        let next = match status.watching.as_ref().and_then(|w| status.rooms.iter().position(|r| r == w)) {
+            Some(i) => status.rooms[(i + 1) % status.rooms.len()].clone(),
+            None => status.rooms[0].clone(),
+        };
+        info!("Watching {}", next);
+        lf_events.single_write(LifeformEvent::Clear);
+        cmd_out.single_write(Pack::new(Cmd::Watch(next.clone()), Dest::All));
+        status.watching = Some(next);
+    }
+}
@@ -31,8 +31,11 @@ impl<'s> System<'s> for WalkAnimationSystem {
     );
 
     fn run(&mut self, (mut sprite_renders, mut anims, players, entities, time): Self::SystemData) {
-        for item in self.delete_list.pop() {
-            anims.remove(item);
+        for item in self.delete_list.drain(..) {
+            // Someone may have started a new walk since
+            if anims.get(item).map_or(false, |anim| anim.delete()) {
+                anims.remove(item);
+            }
         }
         
         for (e, sprite_render, anim, player) in (&entities, &mut sprite_renders, &mut anims, &players).join() {
@@ -1,9 +1,13 @@
 pub mod client;
 pub use self::client::InputSystem;
 pub use self::client::InputSystemBundle;
+pub use self::client::InterpolateSystem;
 pub use self::client::MeleeAnimationSystem;
 pub use self::client::MoveSystem;
 pub use self::client::WalkAnimationSystem;
 
 pub mod server;
+
+mod replay;
+pub use self::replay::ReplaySystem;
 pub use self::server::AuthSystem;
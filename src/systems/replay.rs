@@ -0,0 +1,201 @@
+use amethyst::{
+    ecs::{System, SystemData, World, Write},
+    network::simulation::{NetworkSimulationEvent, TransportResource},
+    shrev::EventChannel,
+};
+use log::{info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::capture::{self, Entry, Record};
+use crate::constants;
+use crate::network::{Cmd, Pack};
+use crate::resources::Challenges;
+
+/// Stands in for the network bundle. Feeds a capture to the `TcpSystem` as if
+/// it came off the wire, at the times it was recorded, and checks what gets
+/// sent back against what was sent when the capture was made. Timing is never
+/// exact, so sends only have to land within `REPLAY_WINDOW_MS` of the record.
+pub struct ReplaySystem {
+    records: VecDeque<Record>,
+    expected: HashMap<SocketAddr, VecDeque<(u64, Cmd)>>, // Recorded outbound and when, per peer
+    challenges: HashMap<SocketAddr, VecDeque<String>>, // What a server asked each peer to sign
+    start: Option<Instant>,
+    matched: u64,
+    diverged: u64,
+    done: bool,
+}
+
+impl ReplaySystem {
+    pub fn new(path: &str) -> std::io::Result<Self> {
+        let records = capture::load(path)?;
+        info!("Loaded {} records from {}", records.len(), path);
+        Self::from_records(records, path)
+    }
+
+    fn from_records(records: Vec<Record>, path: &str) -> std::io::Result<Self> {
+        // Packs were recorded with the encryption off, played back in the clear they'd just get turned away
+        if records.iter().any(|r| matches!(r.entry, Entry::Handshake)) {
+            let reason = format!("{} was captured over an encrypted connection, it can't be replayed. Capture again with encrypt off", path);
+            return Err(Error::new(ErrorKind::InvalidData, reason));
+        }
+        let mut challenges = HashMap::<SocketAddr, VecDeque<String>>::new();
+        for record in records.iter() {
+            if let Entry::Out(Pack { cmd: Cmd::Challenge(challenge), .. }) = &record.entry {
+                challenges.entry(record.peer).or_insert_with(VecDeque::new).push_back(challenge.clone());
+            }
+        }
+        Ok(Self {
+            records: records.into_iter().collect(),
+            expected: HashMap::<SocketAddr, VecDeque<(u64, Cmd)>>::new(),
+            challenges,
+            start: None,
+            matched: 0,
+            diverged: 0,
+            done: false,
+        })
+    }
+
+    /// Play everything that's due. Outbound records get read ahead by the
+    /// window, they might go out a bit early.
+    fn advance(&mut self, now_ms: u64, channel: &mut EventChannel<NetworkSimulationEvent>) {
+        let mut n = 0;
+        while let Some(record) = self.records.get(n) {
+            if record.at_ms > now_ms + constants::REPLAY_WINDOW_MS {
+                break;
+            }
+            let due = match record.entry {
+                Entry::Out(_) => true,
+                _ => record.at_ms <= now_ms,
+            };
+            if !due {
+                n += 1;
+                continue;
+            }
+            let record = self.records.remove(n).unwrap();
+            match record.entry {
+                Entry::Connect => channel.single_write(NetworkSimulationEvent::Connect(record.peer)),
+                Entry::Disconnect => channel.single_write(NetworkSimulationEvent::Disconnect(record.peer)),
+                Entry::In(pack) => match pack.to_bin() {
+                    Ok(bin) => channel.single_write(NetworkSimulationEvent::Message(record.peer, bin.into())),
+                    Err(e) => warn!("Could not serialize recorded pack {:?}: {:?}", pack, e),
+                },
+                Entry::Out(pack) => self.expected.entry(record.peer).or_insert_with(VecDeque::new).push_back((record.at_ms, pack.cmd)),
+                Entry::Handshake => (),
+            }
+        }
+    }
+
+    /// Line up what we just sent with what was sent last time. Anything in
+    /// the window counts, a pack a frame late is still the same pack.
+    fn check(&mut self, peer: SocketAddr, cmd: Cmd) {
+        let queue = self.expected.entry(peer).or_insert_with(VecDeque::new);
+        match queue.iter().position(|(_, expected)| *expected == cmd) {
+            Some(n) => {
+                queue.remove(n);
+                self.matched += 1;
+            },
+            None => {
+                self.diverged += 1;
+                warn!("Replay sent {:?} to {} which is not in the capture around now", cmd, peer);
+            },
+        }
+    }
+
+    /// Recorded packs that should have gone out by now and didn't
+    fn overdue(&mut self, now_ms: u64) {
+        for (peer, queue) in self.expected.iter_mut() {
+            while queue.front().map_or(false, |(at_ms, _)| at_ms + constants::REPLAY_WINDOW_MS < now_ms) {
+                let (_, expected) = queue.pop_front().unwrap();
+                self.diverged += 1;
+                warn!("Replay never sent {:?} to {}, the capture has it", expected, peer);
+            }
+        }
+    }
+}
+
+impl<'a> System<'a> for ReplaySystem {
+    type SystemData = (
+        Write<'a, EventChannel<NetworkSimulationEvent>>,
+        Write<'a, TransportResource>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        // A server asks for the same signatures it did last time. Client captures have none.
+        if !self.challenges.is_empty() {
+            world.insert(Challenges::from_capture(std::mem::take(&mut self.challenges)));
+        }
+    }
+
+    fn run(&mut self, (mut channel, mut net): Self::SystemData) {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let now_ms = start.elapsed().as_millis() as u64;
+        self.advance(now_ms, &mut channel);
+
+        // Nothing is listening on the other end, take what the TcpSystem sent
+        for message in net.drain_messages(|_| true) {
+            for pack in Pack::from_bin_many(message.payload.to_vec()) {
+                match pack {
+                    Ok(pack) => self.check(message.destination, pack.cmd),
+                    Err(e) => warn!("Replay sent a malformed pack to {}: {:?}", message.destination, e),
+                }
+            }
+        }
+        self.overdue(now_ms);
+
+        // Done once the capture's played and everything it sent has turned up or run out of time
+        if self.records.is_empty() && self.expected.values().all(|q| q.is_empty()) && !self.done {
+            self.done = true;
+            info!("Replay finished: {} packs matched, {} diverged", self.matched, self.diverged);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Dest;
+
+    fn peer() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 4000))
+    }
+
+    fn out(at_ms: u64, cmd: Cmd) -> Record {
+        Record { at_ms, peer: peer(), entry: Entry::Out(Pack::new(cmd, Dest::Ip(peer()))) }
+    }
+
+    #[test]
+    fn sends_a_frame_off_still_match() {
+        let mut replay = ReplaySystem::from_records(vec![out(0, Cmd::Pong(1)), out(20, Cmd::Pong(2))], "test").unwrap();
+        let mut channel = EventChannel::<NetworkSimulationEvent>::new();
+
+        // Both read ahead, the second one goes out early and the first one late
+        replay.advance(0, &mut channel);
+        replay.check(peer(), Cmd::Pong(2));
+        replay.overdue(0);
+        replay.advance(40, &mut channel);
+        replay.check(peer(), Cmd::Pong(1));
+        replay.overdue(40);
+        assert_eq!((replay.matched, replay.diverged), (2, 0));
+    }
+
+    #[test]
+    fn sends_that_never_come_diverge() {
+        let mut replay = ReplaySystem::from_records(vec![out(0, Cmd::Pong(1))], "test").unwrap();
+        let mut channel = EventChannel::<NetworkSimulationEvent>::new();
+
+        replay.advance(0, &mut channel);
+        replay.check(peer(), Cmd::Pong(7));
+        replay.overdue(constants::REPLAY_WINDOW_MS + 1);
+        assert_eq!((replay.matched, replay.diverged), (0, 2));
+    }
+
+    #[test]
+    fn encrypted_captures_are_refused() {
+        let records = vec![Record { at_ms: 0, peer: peer(), entry: Entry::Handshake }, out(5, Cmd::Pong(1))];
+        assert!(ReplaySystem::from_records(records, "test").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use amethyst::{
+    core::{SystemDesc, bundle::SystemBundle},
+    ecs::{Write, World, Read, System, SystemData, DispatcherBuilder},
+    shrev::EventChannel,
+    Result,
+};
+
+use log::{info, warn};
+use std::io::{ErrorKind, Read as _, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::{
+    components::{skin_from_str, Action, LifeformType},
+    constants,
+    network::{Pack, Cmd, Dest},
+    resources::{AppConfig, LifeformList, MapList},
+    systems::server::LifeformEvent,
+};
+
+const HELP: &str = "\
+players                          everyone logged in
+kick <uid> [reason]              throw a player out
+tp <uid> <x> <y>                 move a lifeform within its room
+spawn <name> <room> <x> <y>      copy a monster from the maps
+skin <uid> <skin>                change a lifeform's outfit
+say <message>                    tell everyone something
+quit                             close the console
+";
+
+/// Someone on the admin port
+struct Console {
+    stream: TcpStream,
+    addr: SocketAddr,
+    buf: Vec<u8>,
+    authed: bool,
+}
+
+/// Line based console for running the server, only listens on localhost.
+/// The first line has to be `auth <admin_secret>`. Everything it does goes
+/// through the same channels the game uses.
+pub struct AdminSystem {
+    listener: TcpListener,
+    consoles: Vec<Console>,
+}
+
+pub struct AdminSystemBundle {
+    pub listener: TcpListener,
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for AdminSystemBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<()> {
+        builder.add(
+            AdminSystemDesc { listener: self.listener }.build(world),
+            "admin_system",
+            &[],
+        );
+        Ok(())
+    }
+}
+
+pub struct AdminSystemDesc {
+    listener: TcpListener,
+}
+
+impl<'a, 'b> SystemDesc<'a, 'b, AdminSystem> for AdminSystemDesc {
+    fn build(self, world: &mut World) -> AdminSystem {
+        <AdminSystem as System<'_>>::SystemData::setup(world);
+        AdminSystem { listener: self.listener, consoles: Vec::<Console>::new() }
+    }
+}
+
+impl<'a> System<'a> for AdminSystem {
+    type SystemData = (
+        Write<'a, EventChannel<Pack>>,
+        Write<'a, EventChannel<LifeformEvent>>,
+        Read<'a, LifeformList>,
+        Read<'a, MapList>,
+        Read<'a, AppConfig>,
+    );
+
+    fn run(&mut self, (mut cmd_out, mut lf, pl, maps, conf): Self::SystemData) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!("Could not set up admin console for {}: {}", addr, e);
+                        continue;
+                    }
+                    info!("Admin console opened from {}", addr);
+                    self.consoles.push(Console { stream, addr, buf: Vec::<u8>::new(), authed: false });
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Admin console accept failed: {}", e);
+                    break;
+                },
+            }
+        }
+
+        self.consoles = self.consoles.drain(..)
+            .filter_map(|mut console| match serve(&mut console, &conf, &pl, &maps, &mut lf, &mut cmd_out) {
+                true => Some(console),
+                false => None,
+            })
+            .collect();
+    }
+}
+
+/// Answer whatever a console sent, false once it should be closed
+fn serve(
+    console: &mut Console,
+    conf: &AppConfig,
+    pl: &LifeformList,
+    maps: &MapList,
+    lf: &mut EventChannel<LifeformEvent>,
+    cmd_out: &mut EventChannel<Pack>,
+) -> bool {
+    let lines = match receive(console) {
+        Ok(lines) => lines,
+        Err(e) => {
+            info!("Admin console {} closed: {}", console.addr, e);
+            return false;
+        },
+    };
+    for line in lines {
+        let reply = if console.authed {
+            info!("Admin {}: {}", console.addr, line);
+            match line.as_str() {
+                "quit" => return false,
+                _ => command(&line, pl, maps, lf, cmd_out),
+            }
+        } else if line == format!("auth {}", conf.admin_secret) {
+            console.authed = true;
+            "ok, type help for commands".to_string()
+        } else {
+            warn!("Admin console {} gave the wrong secret", console.addr);
+            let _ = console.stream.write_all(b"denied\n");
+            return false;
+        };
+        if console.stream.write_all(format!("{}\n", reply.trim_end()).as_bytes()).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whole lines that have come in, anything unfinished waits in `buf`
+fn receive(console: &mut Console) -> std::result::Result<Vec<String>, String> {
+    let mut chunk = [0u8; 1024];
+    loop {
+        match console.stream.read(&mut chunk) {
+            Ok(0) => return Err("hung up".to_string()),
+            Ok(n) => console.buf.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(format!("{}", e)),
+        }
+    }
+
+    let mut lines = Vec::<String>::new();
+    while let Some(end) = console.buf.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = console.buf.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line).trim().to_string();
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    if console.buf.len() > constants::MAX_ADMIN_LINE {
+        return Err("line too long".to_string());
+    }
+    Ok(lines)
+}
+
+/// Carry out one console line and say how it went
+fn command(
+    line: &str,
+    pl: &LifeformList,
+    maps: &MapList,
+    lf: &mut EventChannel<LifeformEvent>,
+    cmd_out: &mut EventChannel<Pack>,
+) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let uid = |n: usize| words.get(n).and_then(|w| w.parse::<u64>().ok());
+    let num = |n: usize| words.get(n).and_then(|w| w.parse::<f32>().ok());
+    let rest = |n: usize| words.get(n..).map(|w| w.join(" ")).unwrap_or_default();
+
+    match (words[0], words.len()) {
+        ("help", _) => HELP.to_string(),
+        ("players", _) => {
+            let players: Vec<String> = pl.list.iter()
+                .flatten()
+                .filter(|lifeform| lifeform.kind == LifeformType::Player)
+                .map(|p| format!(
+                    "{} {} {} ({}, {}) hp {} {}",
+                    p.id(), p.name, p.room, p.x, p.y, p.hp,
+                    p.ip.map_or("held".to_string(), |ip| ip.to_string()),
+                ))
+                .collect();
+            match players.len() {
+                0 => "nobody's here".to_string(),
+                _ => players.join("\n"),
+            }
+        },
+        ("kick", n) if n >= 2 => {
+            let player = match uid(1).and_then(|uid| pl.get_from_id(uid)) {
+                Some(player) => player,
+                None => return format!("err: no lifeform {}", words[1]),
+            };
+            let ip = match player.ip {
+                Some(ip) => ip,
+                None => return format!("err: {} isn't connected", player.name),
+            };
+            let reason = match rest(2).as_str() {
+                "" => "Kicked by an admin".to_string(),
+                reason => reason.to_string(),
+            };
+            cmd_out.single_write(Pack::new(Cmd::Kicked(reason), Dest::Ip(ip)));
+            format!("ok, kicking {}", player.name)
+        },
+        ("tp", 4) => match (uid(1).and_then(|uid| pl.get_from_id(uid)), num(2), num(3)) {
+            (Some(lifeform), Some(x), Some(y)) => {
+                lf.single_write(LifeformEvent::Teleport(lifeform.id(), x, y));
+                format!("ok, moving {} to ({}, {})", lifeform.name, x, y)
+            },
+            (None, _, _) => format!("err: no lifeform {}", words[1]),
+            _ => "err: x and y have to be numbers".to_string(),
+        },
+        ("spawn", 5) => {
+            let mut monster = match maps.template(words[1]) {
+                Some(monster) => monster.clone(),
+                None => return format!("err: no monster called {} on any map", words[1]),
+            };
+            if !maps.list.contains_key(words[2]) {
+                return format!("err: no room called {}", words[2]);
+            }
+            match (num(3), num(4)) {
+                (Some(x), Some(y)) => {
+                    monster.x = x;
+                    monster.y = y;
+                    lf.single_write(LifeformEvent::Spawn(monster, words[2].to_string()));
+                    format!("ok, spawning {}", words[1])
+                },
+                _ => "err: x and y have to be numbers".to_string(),
+            }
+        },
+        ("skin", 3) => match (uid(1).and_then(|uid| pl.get_from_id(uid)), skin_from_str(words[2])) {
+            (Some(lifeform), Some(skin)) => {
+                let name = lifeform.name.clone();
+                lf.single_write(LifeformEvent::Action(Action::ChangeOutfit(skin), lifeform));
+                format!("ok, {} is now wearing {}", name, words[2])
+            },
+            (None, _) => format!("err: no lifeform {}", words[1]),
+            (_, None) => format!("err: no skin called {}", words[2]),
+        },
+        ("say", n) if n >= 2 => {
+            cmd_out.single_write(Pack::new(Cmd::Message(rest(1)), Dest::All));
+            "ok".to_string()
+        },
+        _ => format!("err: don't know how to {}, try help", line),
+    }
+}
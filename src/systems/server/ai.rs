@@ -9,7 +9,7 @@ use amethyst::{
 use std::time::Instant;
 
 use crate::{
-    resources::{LifeformList, MapList},
+    resources::{ActionKind, LifeformList, MapList, Metrics},
     components::{LifeformType, Action, LifeformComponent, get_rand_orientation},
     systems::server::{LifeformEvent},
 };
@@ -118,9 +118,10 @@ impl<'a> System<'a> for AiSystem {
         Write<'a, EventChannel<LifeformEvent>>,
         Read <'a, MapList>,
         Read <'a, LifeformList>,
+        Write<'a, Metrics>,
     );
 
-    fn run(&mut self, (mut actions, maps, lifeforms): Self::SystemData) {
+    fn run(&mut self, (mut actions, maps, lifeforms, mut metrics): Self::SystemData) {
         let now = Instant::now();
 
         if now.duration_since(self.timer).as_millis() >= 1000 {
@@ -138,6 +139,11 @@ impl<'a> System<'a> for AiSystem {
                     );
                     
                     for event in events {
+                        if let LifeformEvent::Action(act, _) = &event {
+                            if let Some(kind) = ActionKind::of(act) {
+                                metrics.ai(kind);
+                            }
+                        }
                         actions.single_write(event); 
                     }
                 }
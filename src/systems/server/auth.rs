@@ -6,25 +6,34 @@ use amethyst::{
     Result, 
 };
 
-use log::info;
+use log::{info, warn, error};
 use crate::{
-    network::{Pack, Cmd, Dest},
-    components::{LifeformComponent},
-    resources::{LifeformList, MapList, LifeformUID},
+    accounts::Accounts,
+    constants,
+    network::{Pack, Cmd, Dest, Hello, ConnectReply, PROTOCOL_VERSION, CAPABILITIES},
+    components::{LifeformComponent, LifeformType},
+    resources::{AppConfig, Challenges, DuplicateLogin, Interest, LifeformList, MapList, LifeformUID, Sessions, Spectators, SpentTickets},
+    transfer::{self, Portal, TransferTicket},
 };
 
+use std::collections::VecDeque;
 use std::net::{SocketAddr};
 use std::iter::{Iterator};
+use std::time::Instant;
 
 /// Events that pertain to the Auth System
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AuthEvent {
-    Connect( String, SocketAddr), 
+    Greet(u32, SocketAddr), // Wants a challenge for their proof, and what protocol they speak
+    Connect(Hello, SocketAddr),
+    Depart(LifeformComponent, Portal), // Stepped into a portal to another realm
+    Watch(String, SocketAddr), // Spectator switching rooms
 }
 
 #[derive(SystemDesc)]
 pub struct AuthSystem {
     event_reader: ReaderId<AuthEvent>,
+    logins: VecDeque<AuthEvent>, // Connects waiting their turn to be checked
 }
 
 
@@ -49,7 +58,7 @@ impl<'a, 'b> SystemDesc<'a, 'b, AuthSystem> for AuthSystemDesc {
         let event_reader = world
             .fetch_mut::<EventChannel<AuthEvent>>()
             .register_reader();
-        AuthSystem{ event_reader }
+        AuthSystem{ event_reader, logins: VecDeque::<AuthEvent>::new() }
     }
 }
 
@@ -60,37 +69,176 @@ impl<'a> System<'a> for AuthSystem {
         Write <'a, LifeformList>,
         Read <'a, MapList>,
         Write <'a, LifeformUID>,
+        Write <'a, Interest>,
+        Write <'a, Sessions>,
+        Read <'a, AppConfig>,
+        Write <'a, SpentTickets>,
+        Write <'a, Spectators>,
+        Write <'a, Challenges>,
+        Write <'a, Accounts>,
     );
 
-    fn run(&mut self, (mut cmd_out, ev, mut pl, _maps, mut id): Self::SystemData) {
+    fn run(&mut self, (mut cmd_out, ev, mut pl, maps, mut id, mut interest, mut sessions, conf, mut spent, mut spectators, mut challenges, mut accounts): Self::SystemData) {
         //   println!("Received event value of: {:?}", event);
+        let now = Instant::now();
+
+        // A login can cost a password hash, only so many a frame and the rest wait their turn
+        let mut events = Vec::<AuthEvent>::new();
         for event in ev.read(&mut self.event_reader) {
+            match event {
+                AuthEvent::Connect(_, ip) if self.logins.len() >= constants::MAX_QUEUED_LOGINS => {
+                    warn!("Too many logins waiting, turning away {}", ip);
+                    let reason = "Server is busy, try again in a moment".to_string();
+                    cmd_out.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), Dest::Ip(*ip)));
+                },
+                AuthEvent::Connect(_, _) => self.logins.push_back(event.clone()),
+                _ => events.push(event.clone()),
+            }
+        }
+        let ready = self.logins.len().min(constants::MAX_LOGINS_PER_FRAME);
+        events.extend(self.logins.drain(..ready));
+
+        for event in events.iter() {
             match event { 
-                AuthEvent::Connect(auth, ip) => {
-                    match authenticate(auth.to_string()) {
-                        Some(s) => {
-                            let player = ready_player_one(*ip, s, id.add());
+                AuthEvent::Greet(version, ip) => {
+                    if mismatched(*version, *ip, &mut cmd_out) {
+                        continue;
+                    }
+                    let challenge = challenges.issue(*ip, now);
+                    cmd_out.single_write(Pack::new(Cmd::Challenge(challenge), Dest::Ip(*ip)));
+                }
+                AuthEvent::Connect(hello, ip) => {
+                    if mismatched(hello.version, *ip, &mut cmd_out) {
+                        continue;
+                    }
+
+                    match authenticate(hello, *ip, &mut accounts, &mut challenges, now) {
+                        Ok(s) => {
+                            let shared: Vec<String> = CAPABILITIES.iter()
+                                .filter(|c| hello.has(c))
+                                .map(|c| c.to_string())
+                                .collect();
+
+                            // Here to watch, not to play
+                            if let Some(room) = &hello.spectate {
+                                if !maps.list.contains_key(room) {
+                                    let reason = format!("No room called {}", room);
+                                    cmd_out.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), Dest::Ip(*ip)));
+                                    continue;
+                                }
+                                info!("{} is spectating {} from {}", s, room, ip);
+                                cmd_out.single_write(
+                                    Pack::new(Cmd::ConnectReply(ConnectReply::Accept(PROTOCOL_VERSION, shared)), Dest::Ip(*ip)));
+                                cmd_out.single_write(Pack::new(Cmd::Rooms(maps.get_rooms()), Dest::Ip(*ip)));
+                                spectate(room, *ip, &maps, &pl, &mut spectators, &mut cmd_out);
+                                continue;
+                            }
+
+                            // Pick up where they left off if they dropped recently
+                            let resumed = match (&hello.ticket, &hello.resume) {
+                                (None, Some(token)) => resume(token, &s, *ip, &mut sessions, &mut pl, &mut interest),
+                                _ => None,
+                            };
+
+                            // Already on from somewhere else, the config says who wins. A held
+                            // lifeform has nobody on it, so it just gets taken over.
+                            let resumed = match (resumed, pl.get_player_by_name(&s)) {
+                                (None, Some(old)) => {
+                                    let policy = match (&conf.duplicate_login, &hello.ticket, old.ip) {
+                                        (DuplicateLogin::RejectNew, _, Some(_)) => DuplicateLogin::RejectNew,
+                                        (_, Some(_), _) => DuplicateLogin::KickOld, // The ticket brings its own lifeform
+                                        (DuplicateLogin::RejectNew, None, None) => DuplicateLogin::HandOver,
+                                        (policy, None, _) => policy.clone(),
+                                    };
+                                    match duplicate(old, *ip, &policy, &mut sessions, &mut pl, &mut interest, &mut cmd_out) {
+                                        Ok(taken) => taken,
+                                        Err(reason) => {
+                                            info!("Turning away {} from {}: {}", s, ip, reason);
+                                            cmd_out.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), Dest::Ip(*ip)));
+                                            continue;
+                                        },
+                                    }
+                                },
+                                (resumed, _) => resumed,
+                            };
+                            let player = match (resumed, &hello.ticket) {
+                                (Some(player), _) => player,
+                                (None, Some(ticket)) => match arrive(ticket, &s, *ip, id.add(), &conf, &mut spent) {
+                                    Ok(player) => {
+                                        pl.add(player.clone());
+                                        player
+                                    },
+                                    Err(reason) => {
+                                        info!("Turning away {} from {}: {}", s, ip, reason);
+                                        cmd_out.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), Dest::Ip(*ip)));
+                                        continue;
+                                    },
+                                },
+                                (None, None) => {
+                                    let player = ready_player_one(*ip, s, id.add());
+                                    pl.add(player.clone());
+                                    player
+                                },
+                            };
 
                             cmd_out.single_write(
-                                Pack::new(Cmd::InsertPlayer1(player.clone()), Dest::Ip(player.ip())));
-    
+                                Pack::new(Cmd::ConnectReply(ConnectReply::Accept(PROTOCOL_VERSION, shared)), Dest::Ip(*ip)));
+
                             cmd_out.single_write(
-                                Pack::new(Cmd::InsertPlayer(player.clone()), Dest::AllExcept(player.ip())));
+                                Pack::new(Cmd::InsertPlayer1(player.clone()), Dest::Ip(player.ip())));
                             
+                            match maps.info(&player.room) {
+                                Some(info) => cmd_out.single_write(
+                                    Pack::new(Cmd::TransferMap(info), Dest::Ip(player.ip()))),
+                                None => error!("No map info for {}, {} can't draw it", player.room, player.name),
+                            }
+
                             cmd_out.single_write(
-                                Pack::new(Cmd::TransferMap(player.room.clone()), Dest::Ip(player.ip())));
-                            
-                            // Push the rest of the players
-                            for p in pl.list.iter() {
-                                match p {
-                                    Some(p) => cmd_out.single_write(Pack::new(Cmd::InsertPlayer(p.clone()), Dest::Ip(player.ip()))),
-                                    None => (),
-                                }
+                                Pack::new(Cmd::Session(sessions.issue(player.id())), Dest::Ip(player.ip())));
+
+                            // Push the lifeforms they can see, and them to anyone that can see them
+                            for pack in interest.refresh(&player, &pl) {
+                                cmd_out.single_write(pack);
                             }
-                                
-                            pl.add(player); 
+                            for addr in spectators.watching(&player.room) {
+                                cmd_out.single_write(Pack::new(Cmd::InsertPlayer(player.clone()), Dest::Ip(addr)));
+                            }
+                        },
+                        Err(reason) => {
+                            info!("Turning away {}: {}", ip, reason);
+                            cmd_out.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), Dest::Ip(*ip)));
                         },
-                        None => (),
+                    }
+                }
+                AuthEvent::Watch(room, ip) => {
+                    if spectators.room_of(*ip).is_none() {
+                        warn!("{} asked to watch {} but isn't spectating", ip, room);
+                        continue;
+                    }
+                    if !maps.list.contains_key(room) {
+                        info!("{} asked to watch {} which doesn't exist", ip, room);
+                        continue;
+                    }
+                    spectate(room, *ip, &maps, &pl, &mut spectators, &mut cmd_out);
+                }
+                AuthEvent::Depart(player, portal) => {
+                    // Already sent on, or dropped since
+                    let ip = match pl.get_from_id(player.id()).and_then(|p| p.ip) {
+                        Some(ip) => ip,
+                        None => continue,
+                    };
+                    match TransferTicket::issue(player.clone(), &conf.server_ip, portal, &conf.realm_key) {
+                        Ok(ticket) => {
+                            info!("Sending {} ({}) to {}", player.name, player.id(), portal.realm);
+                            cmd_out.single_write(Pack::new(Cmd::Transfer(portal.realm.clone(), ticket), Dest::Ip(ip)));
+
+                            // They live over there now
+                            sessions.revoke(player.id());
+                            pl.remove_with_id(player.id());
+                            interest.forget(player.id());
+                            cmd_out.single_write(Pack::new(Cmd::RemovePlayer(player.id()), Dest::AllExcept(ip)));
+                        },
+                        Err(e) => error!("Could not give {} a ticket to {}: {}", player.name, portal.realm, e),
                     }
                 }
             }
@@ -98,20 +246,174 @@ impl<'a> System<'a> for AuthSystem {
     }
 }
 
-fn authenticate(proof: String) -> Option<String> {
-    let v: Vec<&str> = proof.rsplit(' ').collect();
+/// Turn them away if they don't speak our protocol
+fn mismatched(version: u32, ip: SocketAddr, cmd_out: &mut EventChannel<Pack>) -> bool {
+    if version == PROTOCOL_VERSION {
+        return false;
+    }
+    info!("Client {} speaks protocol {}, we speak {}", ip, version, PROTOCOL_VERSION);
+    let reason = format!("Protocol version mismatch, server is on {}", PROTOCOL_VERSION);
+    cmd_out.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), Dest::Ip(ip)));
+    true
+}
+
+/// Who the proof says they are, if the backend agrees. Makes the account
+/// first if they asked for one.
+fn authenticate(
+    hello: &Hello,
+    ip: SocketAddr,
+    accounts: &mut Accounts,
+    challenges: &mut Challenges,
+    now: Instant,
+) -> std::result::Result<String, String> {
+    let v: Vec<&str> = hello.proof.splitn(3, ' ').collect();
 
     if v.len() != 3 {
-        info!("Proof Package not correct format {:?}", v); 
-        return None;
+        info!("Proof Package not correct format"); 
+        return Err("Proof should be a name, a challenge and a secret".to_string());
     }
     
-    info!("Name: {}, time: {}, Signature: {}", v[2], v[1], v[0]); 
-    // Verify player here
-    // |
-    // |
-    // Verify player here
-    Some(v[2].to_string())
+    // Never the secret, it might be a password
+    info!("Name: {}, challenge: {}", v[0], v[1]); 
+    if accounts.0.needs_challenge() {
+        challenges.answer(ip, v[1], now)?;
+    }
+    match hello.register {
+        true => accounts.0.register(v[0], v[2])?,
+        false => accounts.0.login(v[0], v[1], v[2])?,
+    }
+    Ok(v[0].to_string())
+}
+
+/// Hand a held lifeform over to the new connection, if the token is good and it's theirs
+fn resume(
+    token: &str,
+    name: &str,
+    ip: SocketAddr,
+    sessions: &mut Sessions,
+    pl: &mut LifeformList,
+    interest: &mut Interest,
+) -> Option<LifeformComponent> {
+    let uid = match sessions.holding(token) {
+        Some(uid) => uid,
+        None => {
+            info!("{} tried to resume with an unknown or expired token", name);
+            return None;
+        },
+    };
+
+    match pl.get_from_id(uid) {
+        Some(player) if player.name == name => {
+            info!("Resuming {} ({}) on {}", name, uid, ip);
+            sessions.resume(token);
+            pl.rebind(uid, Some(ip));
+            interest.blind(uid); // Their client starts from nothing
+            pl.get_from_id(uid)
+        },
+        _ => {
+            info!("Resume token for {} does not match their lifeform", name);
+            None
+        },
+    }
+}
+
+/// Someone logged in as a player that's already on. Deals with the old
+/// session the way the config says and hands back the lifeform if the new
+/// connection takes it over. Err if the new one gets turned away instead.
+fn duplicate(
+    old: LifeformComponent,
+    ip: SocketAddr,
+    policy: &DuplicateLogin,
+    sessions: &mut Sessions,
+    pl: &mut LifeformList,
+    interest: &mut Interest,
+    cmd_out: &mut EventChannel<Pack>,
+) -> std::result::Result<Option<LifeformComponent>, String> {
+    let uid = old.id();
+    if let (DuplicateLogin::RejectNew, Some(_)) = (policy, old.ip) {
+        return Err(format!("{} is already logged in", old.name));
+    }
+
+    info!("{} ({}) logged in again from {}, {:?}", old.name, uid, ip, policy);
+    let (kicked, told) = match policy {
+        DuplicateLogin::HandOver => ("Your character was taken over by a new login", "Took over from your other session"),
+        _ => ("You logged in from somewhere else", "Your other session was logged out"),
+    };
+    if let Some(addr) = old.ip {
+        cmd_out.single_write(Pack::new(Cmd::Kicked(kicked.to_string()), Dest::Ip(addr)));
+    }
+    cmd_out.single_write(Pack::new(Cmd::Message(told.to_string()), Dest::Ip(ip)));
+    sessions.revoke(uid);
+
+    match policy {
+        DuplicateLogin::HandOver => {
+            // Same as a resume, their client starts from nothing
+            pl.rebind(uid, Some(ip));
+            interest.blind(uid);
+            Ok(pl.get_from_id(uid))
+        },
+        _ => {
+            pl.remove_with_id(uid);
+            interest.forget(uid);
+            cmd_out.single_write(Pack::new(Cmd::RemovePlayer(uid), Dest::All));
+            Ok(None)
+        },
+    }
+}
+
+/// Show a spectator a room, the map and everything in it
+fn spectate(
+    room: &String,
+    ip: SocketAddr,
+    maps: &MapList,
+    pl: &LifeformList,
+    spectators: &mut Spectators,
+    cmd_out: &mut EventChannel<Pack>,
+) {
+    spectators.watch(ip, room.clone());
+    match maps.info(room) {
+        Some(info) => cmd_out.single_write(Pack::new(Cmd::TransferMap(info), Dest::Ip(ip))),
+        None => error!("No map info for {}, spectator {} can't draw it", room, ip),
+    }
+    for kind in [LifeformType::Player, LifeformType::Monster].iter() {
+        for uid in pl.in_room(room, kind.clone()).cloned().unwrap_or_default() {
+            if let Some(lifeform) = pl.get_from_id(uid) {
+                cmd_out.single_write(Pack::new(Cmd::InsertPlayer(lifeform), Dest::Ip(ip)));
+            }
+        }
+    }
+}
+
+/// Someone another realm sent over, they keep their lifeform but start at our entry point
+fn arrive(
+    ticket: &TransferTicket,
+    name: &str,
+    ip: SocketAddr,
+    uid: u64,
+    conf: &AppConfig,
+    spent: &mut SpentTickets,
+) -> std::result::Result<LifeformComponent, String> {
+    ticket.check(&conf.server_ip, &conf.trusted_realms)?;
+    let carried = &ticket.body.lifeform;
+    if carried.name != name {
+        return Err(format!("Ticket is for {}", carried.name));
+    }
+    let entry = conf.entry_points.iter()
+        .find(|e| e.name == ticket.body.entry)
+        .ok_or_else(|| format!("No entry point called {}", ticket.body.entry))?;
+    if !spent.spend(ticket.body.id, ticket.body.expires, transfer::unix_now()) {
+        return Err("Ticket has already been used".to_string());
+    }
+
+    info!("{} arrived from {} at {}", name, ticket.body.from, entry.name);
+    let mut player = LifeformComponent::new_player(name.to_string(), ip, uid);
+    player.room = entry.room.clone();
+    player.x = entry.x;
+    player.y = entry.y;
+    player.orientation = carried.orientation.clone();
+    player.skin = carried.skin.clone();
+    player.hp = carried.hp;
+    Ok(player)
 }
 
 fn ready_player_one(ip: SocketAddr, name: String, id: u64) -> LifeformComponent {
@@ -120,3 +422,147 @@ fn ready_player_one(ip: SocketAddr, name: String, id: u64) -> LifeformComponent
     // Dig through database to find the correct player by name = name 
     LifeformComponent::new_player(name, ip, id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AuthBackend;
+    use crate::signing;
+    use crate::transfer::EntryPoint;
+    use amethyst::ecs::{RunNow, WorldExt};
+
+    const FROM: &str = "127.0.0.1:3460";
+    const TO: &str = "127.0.0.1:3461";
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// An auth system on its own, as one realm with this config
+    fn realm(conf: AppConfig) -> (World, AuthSystem, ReaderId<Pack>) {
+        let mut world = World::new();
+        world.insert(Accounts::open(&conf).unwrap());
+        world.insert(conf);
+        let system = AuthSystemDesc::default().build(&mut world);
+        let reader = world.fetch_mut::<EventChannel<Pack>>().register_reader();
+        (world, system, reader)
+    }
+
+    /// Run one event through, everything it sent comes back
+    fn handle(world: &mut World, system: &mut AuthSystem, reader: &mut ReaderId<Pack>, event: AuthEvent) -> Vec<Cmd> {
+        world.write_resource::<EventChannel<AuthEvent>>().single_write(event);
+        system.run_now(world);
+        world.read_resource::<EventChannel<Pack>>().read(reader).map(|p| p.cmd.clone()).collect()
+    }
+
+    /// Greet, sign the challenge and say hello with the ticket
+    fn arrive_with(
+        world: &mut World,
+        system: &mut AuthSystem,
+        reader: &mut ReaderId<Pack>,
+        ip: SocketAddr,
+        key: &str,
+        ticket: &TransferTicket,
+    ) -> Vec<Cmd> {
+        let challenge = handle(world, system, reader, AuthEvent::Greet(PROTOCOL_VERSION, ip)).into_iter()
+            .find_map(|cmd| match cmd {
+                Cmd::Challenge(challenge) => Some(challenge),
+                _ => None,
+            })
+            .unwrap();
+        let mut hello = Hello::new(signing::proof("Traveller", &challenge, key).unwrap());
+        hello.ticket = Some(ticket.clone());
+        handle(world, system, reader, AuthEvent::Connect(hello, ip))
+    }
+
+    #[test]
+    fn tickets_carry_players_between_realms_once() {
+        let (realm_key, realm_public) = signing::generate_key().unwrap();
+        let (player_key, player_public) = signing::generate_key().unwrap();
+
+        // Where they're leaving from
+        let mut conf = AppConfig::default();
+        conf.server_ip = FROM.to_string();
+        conf.realm_key = realm_key;
+        let (mut world, mut system, mut reader) = realm(conf);
+        let traveller = LifeformComponent::new_player("Traveller".to_string(), addr(4000), 1);
+        world.write_resource::<LifeformList>().add(traveller.clone());
+        let portal = Portal { room: "town".to_string(), x: 8.0, y: 24.0, realm: TO.to_string(), entry: "gate".to_string() };
+        let ticket = handle(&mut world, &mut system, &mut reader, AuthEvent::Depart(traveller, portal)).into_iter()
+            .find_map(|cmd| match cmd {
+                Cmd::Transfer(realm, ticket) if realm == TO => Some(ticket),
+                _ => None,
+            })
+            .expect("no ticket");
+        assert!(world.read_resource::<LifeformList>().get_from_id(1).is_none());
+
+        // Where they're going, which trusts the first realm's key and knows the player's
+        let mut conf = AppConfig::default();
+        conf.server_ip = TO.to_string();
+        conf.auth = AuthBackend::Keys;
+        conf.trusted_realms = vec![realm_public];
+        conf.entry_points = vec![EntryPoint { name: "gate".to_string(), room: "town".to_string(), x: 40.0, y: 40.0 }];
+        conf.player_keys.insert("Traveller".to_string(), player_public);
+        let (mut world, mut system, mut reader) = realm(conf);
+
+        let sent = arrive_with(&mut world, &mut system, &mut reader, addr(5000), &player_key, &ticket);
+        assert!(sent.iter().any(|cmd| matches!(cmd, Cmd::ConnectReply(ConnectReply::Accept(_, _)))), "{:?}", sent);
+        let player = world.read_resource::<LifeformList>().get_player_by_name("Traveller").unwrap();
+        assert_eq!((player.room.as_str(), player.x, player.y), ("town", 40.0, 40.0));
+
+        // They log out and someone tries the same ticket again
+        world.write_resource::<LifeformList>().remove_with_id(player.id());
+        let sent = arrive_with(&mut world, &mut system, &mut reader, addr(5001), &player_key, &ticket);
+        assert_eq!(sent, vec![Cmd::ConnectReply(ConnectReply::Reject("Ticket has already been used".to_string()))]);
+    }
+
+    #[test]
+    fn reject_new_only_rejects_live_sessions() {
+        let mut conf = AppConfig::default();
+        conf.auth = AuthBackend::Dev;
+        conf.duplicate_login = DuplicateLogin::RejectNew;
+        let (mut world, mut system, mut reader) = realm(conf);
+        {
+            // Dropped a moment ago, held for them
+            let mut pl = world.write_resource::<LifeformList>();
+            pl.add(LifeformComponent::new_player("Turnip".to_string(), addr(4000), 1));
+            pl.rebind(1, None);
+        }
+
+        let hello = || Hello::new("Turnip - -".to_string());
+        let sent = handle(&mut world, &mut system, &mut reader, AuthEvent::Connect(hello(), addr(5000)));
+        assert!(sent.iter().any(|cmd| matches!(cmd, Cmd::ConnectReply(ConnectReply::Accept(_, _)))), "{:?}", sent);
+        assert_eq!(world.read_resource::<LifeformList>().get_from_id(1).unwrap().ip, Some(addr(5000)));
+
+        // Now someone's on it
+        let sent = handle(&mut world, &mut system, &mut reader, AuthEvent::Connect(hello(), addr(5001)));
+        assert_eq!(sent, vec![Cmd::ConnectReply(ConnectReply::Reject("Turnip is already logged in".to_string()))]);
+        assert_eq!(world.read_resource::<LifeformList>().get_from_id(1).unwrap().ip, Some(addr(5000)));
+    }
+
+    #[test]
+    fn logins_wait_their_turn() {
+        let mut conf = AppConfig::default();
+        conf.auth = AuthBackend::Dev;
+        let (world, mut system, mut reader) = realm(conf);
+        let accepted = |sent: &Vec<Cmd>| sent.iter().filter(|cmd| matches!(cmd, Cmd::ConnectReply(ConnectReply::Accept(_, _)))).count();
+
+        let logins = constants::MAX_LOGINS_PER_FRAME * 2 + 1;
+        for n in 0..logins {
+            let hello = Hello::new(format!("Player{} - -", n));
+            world.write_resource::<EventChannel<AuthEvent>>().single_write(AuthEvent::Connect(hello, addr(5000 + n as u16)));
+        }
+        system.run_now(&world);
+        let sent: Vec<Cmd> = world.read_resource::<EventChannel<Pack>>().read(&mut reader).map(|p| p.cmd.clone()).collect();
+        assert_eq!(accepted(&sent), constants::MAX_LOGINS_PER_FRAME);
+
+        // Everyone gets in eventually
+        let mut total = accepted(&sent);
+        for _ in 0..logins {
+            system.run_now(&world);
+            let sent: Vec<Cmd> = world.read_resource::<EventChannel<Pack>>().read(&mut reader).map(|p| p.cmd.clone()).collect();
+            total += accepted(&sent);
+        }
+        assert_eq!(total, logins);
+    }
+}
@@ -8,15 +8,19 @@ use amethyst::{
 use log::info;
 
 use crate::{
-    network::{Pack, Cmd, Dest},
-    components::{Action, get_outfit, LifeformComponent, LifeformType},
-    resources::{LifeformList, MapList},
+    network::{Pack, Cmd, Dest, Ack},
+    components::{Action, get_outfit, LifeformComponent, LifeformDelta, LifeformType, Monster},
+    resources::{AppConfig, Interest, LifeformList, LifeformUID, MapList, Spectators},
+    systems::server::AuthEvent,
 };
 
 #[derive(Debug)]
 pub enum LifeformEvent {
     RemovePlayer(u64),
     Action(Action, LifeformComponent),
+    Input(u32, Action, LifeformComponent), // Action from a client that wants an Ack
+    Teleport(u64, f32, f32), // Somewhere else in the same room
+    Spawn(Monster, String), // New monster in this room
 }
 
 /// Lifeform manager system.
@@ -56,42 +60,134 @@ impl<'a> System<'a> for LifeformSystem {
         Read<'a, EventChannel<LifeformEvent>>,
         Write<'a, LifeformList>,
         Read <'a, MapList>,
+        Write<'a, Interest>,
+        Read<'a, AppConfig>,
+        Write<'a, EventChannel<AuthEvent>>,
+        Write<'a, LifeformUID>,
+        Read<'a, Spectators>,
     );
 
-    fn run(&mut self, (mut cmd_out, events, mut pl, maps): Self::SystemData) {
+    fn run(&mut self, (mut cmd_out, events, mut pl, maps, mut interest, conf, mut auth, mut id, spectators): Self::SystemData) {
         for event in events.read(&mut self.event_reader) {
            match &event {
                 LifeformEvent::Action(act, player_acting) => {
                     // info!("Action from Player: {:?}, Action: {:?}", player_acting, act);
-                    let packs_players = self.act(player_acting.clone(), act, &maps, &pl);
-                    
-                    // If packs come out of the action
-                    for pack in packs_players.0 {
-                        // info!("{:?}", pack);
-                        cmd_out.single_write(pack)
+                    self.update(player_acting.clone(), act, &maps, &mut pl, &mut interest, &mut cmd_out);
+                    portal(player_acting.id(), act, &pl, &conf, &mut auth);
+                },
+                LifeformEvent::Input(seq, act, player_acting) => {
+                    self.update(player_acting.clone(), act, &maps, &mut pl, &mut interest, &mut cmd_out);
+                    portal(player_acting.id(), act, &pl, &conf, &mut auth);
+
+                    // Tell them where they really ended up, even if nothing moved
+                    if let Some(player) = pl.get_from_id(player_acting.id()) {
+                        cmd_out.single_write(Pack::new(Cmd::Ack(Ack::new(*seq, &player)), Dest::Ip(player.ip())));
                     }
+                },
+                LifeformEvent::RemovePlayer(uid) => {
+                    pl.remove_with_id(*uid);
+                    interest.forget(*uid);
+                },
+                LifeformEvent::Teleport(uid, x, y) => {
+                    let mut lifeform = match pl.get_from_id(*uid) {
+                        Some(lifeform) => lifeform,
+                        None => continue,
+                    };
+                    lifeform.x = *x;
+                    lifeform.y = *y;
+                    publish(vec![lifeform], &mut pl, &mut interest, &mut cmd_out);
 
-                    // If a player needs to be replacd  
-                    for player in packs_players.1 {
-                        // info!("{:?}", player);
-                        pl.replace(player); 
+                    // Their own client doesn't take positions from updates, put them there
+                    if let Some(player) = pl.get_from_id(*uid).filter(|p| p.ip.is_some()) {
+                        cmd_out.single_write(Pack::new(Cmd::InsertPlayer1(player.clone()), Dest::Ip(player.ip())));
+                    }
+                },
+                LifeformEvent::Spawn(monster, room) => {
+                    let lifeform = LifeformComponent::new_monster(id.add(), monster, room.clone());
+                    info!("Spawning {} ({}) in {}", lifeform.name, lifeform.id(), room);
+                    pl.add(lifeform.clone());
+                    for pack in interest.refresh(&lifeform, &pl) {
+                        cmd_out.single_write(pack);
+                    }
+                    for addr in spectators.watching(room) {
+                        cmd_out.single_write(Pack::new(Cmd::InsertPlayer(lifeform.clone()), Dest::Ip(addr)));
                     }
                 },
-                LifeformEvent::RemovePlayer(uid) => pl.remove_with_id(*uid), 
             }
         }
     }
 }
 
+/// Put changed lifeforms in the list, only sending what changed
+fn publish(
+    players: Vec<LifeformComponent>,
+    pl: &mut LifeformList,
+    interest: &mut Interest,
+    cmd_out: &mut EventChannel<Pack>,
+) {
+    for mut player in players {
+        // info!("{:?}", player);
+        if let Some(old) = pl.get_from_id(player.id()) {
+            player.rev = old.rev + 1;
+            if let Some(delta) = LifeformDelta::diff(&old, &player) {
+                let uid = player.id();
+                pl.replace(player.clone());
+
+                // Moving changes who can see who
+                if delta.pos.is_some() {
+                    for pack in interest.refresh(&player, pl) {
+                        cmd_out.single_write(pack);
+                    }
+                }
+                cmd_out.single_write(Pack::new(Cmd::UpdateLifeform(delta), Dest::Seen(uid)));
+            }
+        }
+    }
+}
+
+/// Players that walk into a portal get handed to the AuthSystem to send on
+fn portal(uid: u64, act: &Action, pl: &LifeformList, conf: &AppConfig, auth: &mut EventChannel<AuthEvent>) {
+    match act {
+        Action::Move(_) => (),
+        _ => return,
+    }
+    if let Some(player) = pl.get_from_id(uid) {
+        if player.kind != LifeformType::Player {
+            return;
+        }
+        if let Some(portal) = conf.portals.iter().find(|p| p.at(&player)) {
+            auth.single_write(AuthEvent::Depart(player, portal.clone()));
+        }
+    }
+}
+
 impl LifeformSystem {
+    /// Carry out an action and send everyone in the room what changed
+    fn update(
+        &mut self,
+        player_acting: LifeformComponent,
+        act: &Action,
+        maps: &MapList,
+        pl: &mut LifeformList,
+        interest: &mut Interest,
+        cmd_out: &mut EventChannel<Pack>,
+    ) {
+        // Let anyone watching see the swing, hit or miss
+        if let Action::Melee = act {
+            cmd_out.single_write(Pack::new(Cmd::Swing(player_acting.id()), Dest::Seen(player_acting.id())));
+        }
+
+        let players = self.act(player_acting, act, maps, pl);
+        publish(players, pl, interest, cmd_out);
+    }
+
     fn act(&mut self, 
            mut player: LifeformComponent, 
            act: &Action,
            maps: &MapList,
            pl: &LifeformList,
-           )-> (Vec<Pack>, Vec<LifeformComponent>) 
+           )-> Vec<LifeformComponent>
         {
-        let mut pack_out = Vec::<Pack>::new();
         let mut players_out = Vec::<LifeformComponent>::new();
 
         match act {
@@ -104,7 +200,7 @@ impl LifeformSystem {
                     for player_id in players {
                         if let Some(player) = pl.get_from_id(*player_id) {
                             if player.trans() == next_step { 
-                                return (pack_out, players_out)
+                                return players_out
                             }
                         }
                     }
@@ -115,7 +211,7 @@ impl LifeformSystem {
                     for monster_id in monsters {
                         if let Some(monster) = pl.get_from_id(*monster_id) {
                             if monster.trans() == next_step { 
-                                return (pack_out, players_out)
+                                return players_out
                             }
                         }
                     }
@@ -125,18 +221,14 @@ impl LifeformSystem {
                 if maps.get(&player.room).unwrap().allowed_move(&player.trans(), &player.orientation) {
                     // info!("Player Walking"); 
                     player.walk();
-                    players_out.push(player.clone());
-                    let room = player.room.clone();
-                    pack_out.push(Pack::new(Cmd::UpdatePlayer(player), Dest::Room(room)));
+                    players_out.push(player);
                 }
             },
             
             Action::ChangeOutfit(skin) => {
                 player.skin = get_outfit(&skin);
                 //TODO: Make sure skin in legal!
-                players_out.push(player.clone());
-                let rm = player.room.clone();
-                pack_out.push(Pack::new(Cmd::UpdatePlayer(player), Dest::Room(rm)));
+                players_out.push(player);
             },
 
             Action::Melee => {
@@ -146,9 +238,7 @@ impl LifeformSystem {
                     Some(mut victom) => {
                         info!("Direct Hit!");
                         victom.hp(-10.0); // Oh shit
-                        players_out.push(victom.clone());
-                        let rm = player.room.clone();
-                        pack_out.push(Pack::new(Cmd::UpdatePlayer(victom), Dest::Room(rm)));
+                        players_out.push(victom);
                     },
                     None => info!("And a miss!"), 
                 }
@@ -156,13 +246,11 @@ impl LifeformSystem {
             
             Action::Rotate(dir) => {
                 player.orientation = dir.clone();
-                players_out.push(player.clone());
-                let rm = player.room.clone();
-                pack_out.push(Pack::new(Cmd::UpdatePlayer(player), Dest::Room(rm)));
+                players_out.push(player);
             },
             _ => (), 
         };
 
-        (pack_out, players_out)
+        players_out
     }
 }
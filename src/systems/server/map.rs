@@ -0,0 +1,101 @@
+use amethyst::{
+    core::{SystemDesc, bundle::SystemBundle},
+    derive::SystemDesc,
+    ecs::{Write, World, Read, System, SystemData, DispatcherBuilder},
+    shrev::{EventChannel, ReaderId},
+    Result,
+};
+
+use log::{info, warn};
+use crate::{
+    constants,
+    network::{Pack, Cmd, Dest, FileChunk},
+    resources::MapList,
+};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Events that pertain to the Map System
+#[derive(Debug)]
+pub enum MapEvent {
+    Fetch(String, SocketAddr), // Client wants a map file
+    Disconnect(SocketAddr),
+}
+
+/// Sends map files down to clients that don't have them.
+#[derive(SystemDesc)]
+pub struct MapSystem {
+    event_reader: ReaderId<MapEvent>,
+    sent: HashMap<(SocketAddr, String), u32>, // Times each file went out on this connection, they're big
+}
+
+pub struct MapSystemBundle;
+impl<'a, 'b> SystemBundle<'a, 'b> for MapSystemBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<()> {
+        builder.add(
+            MapSystemDesc::default().build(world),
+            "server_map_system",
+            &[],
+        );
+        Ok(())
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct MapSystemDesc;
+
+impl<'a, 'b> SystemDesc<'a, 'b, MapSystem> for MapSystemDesc {
+    fn build(self, world: &mut World) -> MapSystem {
+        <MapSystem as System<'_>>::SystemData::setup(world);
+        let event_reader = world
+            .fetch_mut::<EventChannel<MapEvent>>()
+            .register_reader();
+        MapSystem{ event_reader, sent: HashMap::<(SocketAddr, String), u32>::new() }
+    }
+}
+
+impl<'a> System<'a> for MapSystem {
+    type SystemData = (
+        Write<'a, EventChannel<Pack>>,
+        Read<'a, EventChannel<MapEvent>>,
+        Read<'a, MapList>,
+    );
+
+    fn run(&mut self, (mut cmd_out, events, maps): Self::SystemData) {
+        for event in events.read(&mut self.event_reader) {
+            match event {
+                MapEvent::Fetch(name, ip) => {
+                    let file = match maps.file(name) {
+                        Some(file) => file,
+                        None => {
+                            warn!("{} asked for {} which we don't share", ip, name);
+                            continue;
+                        },
+                    };
+                    // Asking again is fine if their copy didn't hash right, just not forever
+                    let sends = self.sent.entry((*ip, name.clone())).or_insert(0);
+                    if *sends >= constants::MAX_FILE_SENDS {
+                        warn!("{} asked for {} {} times already, ignoring it", ip, name, sends);
+                        continue;
+                    }
+                    *sends += 1;
+
+                    let chunks: Vec<&[u8]> = file.bytes.chunks(constants::FILE_CHUNK_BYTES).collect();
+                    info!("Sending {} to {} in {} chunks", name, ip, chunks.len());
+                    for (index, data) in chunks.iter().enumerate() {
+                        let chunk = FileChunk {
+                            name: name.clone(),
+                            hash: file.hash.clone(),
+                            index: index as u32,
+                            total: chunks.len() as u32,
+                            data: data.to_vec(),
+                        };
+                        cmd_out.single_write(Pack::new(Cmd::FileChunk(chunk), Dest::Ip(*ip)));
+                    }
+                },
+                MapEvent::Disconnect(ip) => self.sent.retain(|(addr, _), _| addr != ip),
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use amethyst::{
+    core::{SystemDesc, bundle::SystemBundle},
+    ecs::{World, Read, System, SystemData, DispatcherBuilder},
+    Result,
+};
+
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Read as _, Write as _};
+use std::net::{TcpListener, TcpStream};
+use std::time::Instant;
+
+use crate::resources::{LifeformList, Metrics, ServerStats};
+
+/// How long a scraper gets to send its request before we give up on it
+const REQUEST_TIMEOUT_MS: u128 = 2000;
+
+/// Plain text metrics in the Prometheus format, over HTTP on a localhost
+/// port. Any request gets the metrics, the path doesn't matter.
+pub struct MetricsSystem {
+    listener: TcpListener,
+    waiting: Vec<(TcpStream, Vec<u8>, Instant)>, // Still sending their request
+}
+
+pub struct MetricsSystemBundle {
+    pub listener: TcpListener,
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for MetricsSystemBundle {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<()> {
+        builder.add(
+            MetricsSystemDesc { listener: self.listener }.build(world),
+            "metrics_system",
+            &[],
+        );
+        Ok(())
+    }
+}
+
+pub struct MetricsSystemDesc {
+    listener: TcpListener,
+}
+
+impl<'a, 'b> SystemDesc<'a, 'b, MetricsSystem> for MetricsSystemDesc {
+    fn build(self, world: &mut World) -> MetricsSystem {
+        <MetricsSystem as System<'_>>::SystemData::setup(world);
+        MetricsSystem { listener: self.listener, waiting: Vec::new() }
+    }
+}
+
+impl<'a> System<'a> for MetricsSystem {
+    type SystemData = (
+        Read<'a, ServerStats>,
+        Read<'a, Metrics>,
+        Read<'a, LifeformList>,
+    );
+
+    fn run(&mut self, (stats, metrics, pl): Self::SystemData) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.waiting.push((stream, Vec::new(), Instant::now())),
+                    Err(e) => warn!("Could not take a metrics request from {}: {}", addr, e),
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Metrics accept failed: {}", e);
+                    break;
+                },
+            }
+        }
+        if self.waiting.is_empty() {
+            return;
+        }
+
+        let mut page: Option<String> = None; // Only worked out if someone's asking
+        self.waiting = self.waiting.drain(..)
+            .filter_map(|(mut stream, mut buf, since)| {
+                let mut chunk = [0u8; 1024];
+                loop {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => return None,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(_) => return None,
+                    }
+                }
+                // Wait for the end of the headers
+                if !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                    if since.elapsed().as_millis() >= REQUEST_TIMEOUT_MS || buf.len() > 8192 {
+                        info!("Dropping a metrics request that never finished");
+                        return None;
+                    }
+                    return Some((stream, buf, since));
+                }
+
+                let body = page.get_or_insert_with(|| render(&stats, &metrics, &pl));
+                let response = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body,
+                );
+                // Small enough to go in one write, the socket buffer takes it
+                if let Err(e) = stream.write_all(response.as_bytes()) {
+                    warn!("Could not send metrics: {}", e);
+                }
+                None
+            })
+            .collect();
+    }
+}
+
+/// Everything we report, in the Prometheus text format
+fn render(stats: &ServerStats, metrics: &Metrics, pl: &LifeformList) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "realm_clients", "gauge", "Connected clients, players and spectators");
+    let _ = writeln!(out, "realm_clients {}", stats.clients);
+
+    // Sorted so the output doesn't jump around between scrapes
+    let mut rooms = BTreeMap::<(String, String), u64>::new();
+    for lifeform in pl.list.iter().flatten() {
+        *rooms.entry((lifeform.room.clone(), format!("{:?}", lifeform.kind))).or_insert(0) += 1;
+    }
+    header(&mut out, "realm_lifeforms", "gauge", "Lifeforms in each room");
+    for ((room, kind), count) in rooms {
+        let _ = writeln!(out, "realm_lifeforms{{room=\"{}\",kind=\"{}\"}} {}", escape(&room), kind, count);
+    }
+
+    let traffic: BTreeMap<_, _> = metrics.traffic.iter().collect();
+    header(&mut out, "realm_packs_total", "counter", "Packs by Cmd and direction");
+    for (cmd, t) in traffic.iter() {
+        let _ = writeln!(out, "realm_packs_total{{cmd=\"{}\",dir=\"in\"}} {}", cmd, t.packs_in);
+        let _ = writeln!(out, "realm_packs_total{{cmd=\"{}\",dir=\"out\"}} {}", cmd, t.packs_out);
+    }
+    header(&mut out, "realm_pack_bytes_total", "counter", "Bytes of packs by Cmd and direction, before batching and encryption");
+    for (cmd, t) in traffic.iter() {
+        let _ = writeln!(out, "realm_pack_bytes_total{{cmd=\"{}\",dir=\"in\"}} {}", cmd, t.bytes_in);
+        let _ = writeln!(out, "realm_pack_bytes_total{{cmd=\"{}\",dir=\"out\"}} {}", cmd, t.bytes_out);
+    }
+    header(&mut out, "realm_wire_bytes_total", "counter", "Bytes on the wire by direction");
+    let _ = writeln!(out, "realm_wire_bytes_total{{dir=\"in\"}} {}", stats.bytes_in);
+    let _ = writeln!(out, "realm_wire_bytes_total{{dir=\"out\"}} {}", stats.bytes_out);
+
+    let ai: BTreeMap<_, _> = metrics.ai_events.iter().map(|(kind, n)| (format!("{:?}", kind), n)).collect();
+    header(&mut out, "realm_ai_events_total", "counter", "Actions the AI has given monsters");
+    for (kind, count) in ai {
+        let _ = writeln!(out, "realm_ai_events_total{{action=\"{}\"}} {}", kind, count);
+    }
+
+    header(&mut out, "realm_frame_seconds", "summary", "Time the dispatcher spends on a frame");
+    let _ = writeln!(out, "realm_frame_seconds_sum {}", stats.tick_total_us as f64 / 1e6);
+    let _ = writeln!(out, "realm_frame_seconds_count {}", stats.ticks);
+    header(&mut out, "realm_frame_seconds_max", "gauge", "Longest frame since the server started");
+    let _ = writeln!(out, "realm_frame_seconds_max {}", stats.tick_max_us as f64 / 1e6);
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Room names go in label values
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
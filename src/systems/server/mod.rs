@@ -12,3 +12,16 @@ pub use self::network::{TcpSystemBundle};
 
 mod ai;
 pub use self::ai::{AiSystemBundle};
+
+mod map;
+pub use self::map::MapSystemBundle;
+pub use self::map::MapEvent;
+
+mod tick;
+pub use self::tick::{TickStartSystem, TickEndSystem};
+
+mod admin;
+pub use self::admin::AdminSystemBundle;
+
+mod metrics;
+pub use self::metrics::MetricsSystemBundle;
@@ -2,15 +2,23 @@ use amethyst::{
     core::{SystemDesc, bundle::SystemBundle},
     ecs::{Read, Write, System, SystemData, World, DispatcherBuilder},
     shrev::{EventChannel, ReaderId}, 
-    network::simulation::{NetworkSimulationEvent, TransportResource, NetworkSimulationTime},
+    network::simulation::{NetworkSimulationEvent, TransportResource, NetworkSimulationTime, UrgencyRequirement, tcp::TcpNetworkResource},
     Result,
 };
 
 use log::{info, warn, error};
-use crate::network::{Pack, Cmd, Dest};
-use crate::resources::{LifeformList};
-use crate::systems::server::{AuthEvent, LifeformEvent};
+use crate::accounts::AuthBackend;
+use crate::capture::{self, Entry, Recorder};
+use crate::constants;
+use crate::components::{Action, LifeformComponent};
+use crate::network::{Pack, Cmd, Dest, Ack, Batch, Batcher, Reassembly};
+use crate::network::{ConnectReply, PROTOCOL_VERSION};
+use crate::resources::{ActionBudget, AppConfig, Interest, Latency, LifeformList, Metrics, ServerStats, Sessions, Spectators};
+use crate::secure::{self, SecureChannel};
+use crate::systems::server::{AuthEvent, LifeformEvent, MapEvent};
 use std::net::{SocketAddr};
+use std::collections::HashMap;
+use std::time::Instant;
 
 #[derive(Debug)]
 pub struct TcpSystemBundle;
@@ -47,6 +55,18 @@ pub struct TcpSystem {
     net_reader: ReaderId<NetworkSimulationEvent>,
     event_reader: ReaderId<Pack>,
     clients: Vec<SocketAddr>,
+    malformed: HashMap<SocketAddr, u32>, // Bad packets per connection
+    partial: Reassembly,                 // Packs cut off at the end of a read
+    logins: HashMap<SocketAddr, u32>,    // Connects per connection, each one can cost a password hash
+    last_heard: HashMap<SocketAddr, Instant>,
+    pinged: HashMap<SocketAddr, (u64, Instant)>, // Ping we're waiting on
+    ping_timer: Instant,
+    nonce: u64,
+    abusers: Vec<SocketAddr>, // Over their action budget too often or told they're kicked, gone next frame
+    kicked: HashMap<SocketAddr, Instant>, // Anything from these gets ignored until the cooldown is up
+    batcher: Batcher,
+    secure: HashMap<SocketAddr, SecureChannel>, // Clients that did a handshake
+    secret: Option<Vec<u8>>,                    // Our static key, loaded on the first handshake
 }
 
 impl TcpSystem {
@@ -59,8 +79,136 @@ impl TcpSystem {
             net_reader,
             event_reader,
             clients: Vec::<SocketAddr>::new(),
+            malformed: HashMap::<SocketAddr, u32>::new(),
+            partial: Reassembly::default(),
+            logins: HashMap::<SocketAddr, u32>::new(),
+            last_heard: HashMap::<SocketAddr, Instant>::new(),
+            pinged: HashMap::<SocketAddr, (u64, Instant)>::new(),
+            ping_timer: Instant::now(),
+            nonce: 0,
+            abusers: Vec::<SocketAddr>::new(),
+            kicked: HashMap::<SocketAddr, Instant>::new(),
+            batcher: Batcher::new(),
+            secure: HashMap::<SocketAddr, SecureChannel>::new(),
+            secret: None,
         }
     }
+
+    /// Forget about a client connection. Their lifeform stays where it is with
+    /// no ip, returns its uid so the caller can hold it or get rid of it.
+    fn drop_client(&mut self, addr: SocketAddr, pl: &mut LifeformList, latency: &mut Latency, spectators: &mut Spectators) -> Option<u64> {
+        self.clients.retain(|&x| x != addr);
+        self.malformed.remove(&addr);
+        self.partial.forget(&addr);
+        self.logins.remove(&addr);
+        self.last_heard.remove(&addr);
+        self.pinged.remove(&addr);
+        self.secure.remove(&addr);
+        latency.remove(addr);
+        if spectators.stop(addr) {
+            return None; // Never had a lifeform
+        }
+
+        match pl.get_from_ip(addr) {
+            Some(player) => {
+                pl.rebind(player.id(), None);
+                Some(player.id())
+            },
+            None => {
+                warn!("Player disconnected that was not on the playerlist");
+                None
+            },
+        }
+    }
+
+    /// Count a bad packet against a client, returns true once they should be kicked
+    fn strike(&mut self, addr: SocketAddr) -> bool {
+        let count = self.malformed.entry(addr).or_insert(0);
+        *count += 1;
+        *count >= constants::MAX_MALFORMED_PACKS
+    }
+
+    /// Our static key from the config, or a throwaway one if there isn't one
+    fn secret_key(&mut self, conf: &AppConfig) -> std::result::Result<Vec<u8>, String> {
+        if self.secret.is_none() {
+            let secret = match conf.secret_key.as_str() {
+                "" => {
+                    let (secret, public) = secure::generate_key()?;
+                    warn!("No secret_key in the config, using a new key for this run. Public key: {}", public);
+                    secret
+                },
+                key => key.to_string(),
+            };
+            self.secret = Some(hex::decode(secret).map_err(|e| format!("Bad secret key: {}", e))?);
+        }
+        Ok(self.secret.clone().unwrap_or_default())
+    }
+
+    /// Deal with the encryption layer. Handshakes get answered here and sealed
+    /// packs come back as the packs inside. Err counts as a malformed pack.
+    fn unseal(
+        &mut self,
+        addr: SocketAddr,
+        pack: Pack,
+        conf: &AppConfig,
+        net: &mut TransportResource,
+        in_packs: &mut EventChannel<Pack>,
+    ) -> std::result::Result<Vec<Pack>, String> {
+        match pack.cmd {
+            Cmd::Handshake(msg) => {
+                let secret = self.secret_key(conf)?;
+                let (channel, reply) = SecureChannel::responder(&secret, &msg)?;
+                self.secure.insert(addr, channel);
+
+                // The answer has to go out in the clear, ahead of anything sealed
+                let bin = reply.to_bin().map_err(|e| format!("{:?}", e))?;
+                net.send_with_requirements(addr, &bin, reply.cmd.delivery(), UrgencyRequirement::OnTick);
+                Ok(Vec::<Pack>::new())
+            },
+            Cmd::Sealed(nonce, sealed) => {
+                let channel = self.secure.get_mut(&addr).ok_or_else(|| "Sealed pack before a handshake".to_string())?;
+                let mut packs = Vec::<Pack>::new();
+                for inner in Pack::from_bin_many(channel.open(nonce, &sealed)?) {
+                    let inner = inner.map_err(|e| format!("{:?}", e))?;
+                    match inner.cmd {
+                        Cmd::Handshake(_) | Cmd::Sealed(_, _) => return Err("Handshake inside a sealed pack".to_string()),
+                        _ => packs.push(inner),
+                    }
+                }
+                Ok(packs)
+            },
+            _ if self.secure.contains_key(&addr) => Err("Plain pack on an encrypted connection".to_string()),
+            Cmd::Connect(_) if conf.encrypt => {
+                let reason = "Server requires encryption, set encrypt: true".to_string();
+                in_packs.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), Dest::Ip(addr)));
+                Ok(Vec::<Pack>::new())
+            },
+            _ if conf.encrypt => Err("Plain pack, server requires encryption".to_string()),
+            _ => Ok(vec![pack]),
+        }
+    }
+
+    /// Check an action against the lifeform's budget, false means drop it
+    fn within_budget(
+        &mut self,
+        addr: SocketAddr,
+        player: &LifeformComponent,
+        act: &Action,
+        budget: &mut ActionBudget,
+        now: Instant,
+    ) -> bool {
+        if budget.allow(player.id(), act, now) {
+            return true;
+        }
+
+        let count = budget.violation(player.id(), now);
+        warn!("{} ({}) is acting too fast, dropped {:?} ({} in a row)", player.name, player.id(), act, count);
+        if count >= constants::MAX_RATE_VIOLATIONS && !self.abusers.contains(&addr) {
+            warn!("Kicking {} for going over their action budget", addr);
+            self.abusers.push(addr);
+        }
+        false
+    }
 }
 
 impl<'a> System<'a> for TcpSystem {
@@ -72,34 +220,94 @@ impl<'a> System<'a> for TcpSystem {
         Read<'a, NetworkSimulationTime>,
         Read<'a, EventChannel<NetworkSimulationEvent>>,
         Write<'a, LifeformList>,
+        Option<Write<'a, TcpNetworkResource>>, // Only there when running over TCP
+        Read<'a, Interest>,
+        Write<'a, Latency>,
+        Read<'a, AppConfig>,
+        Write<'a, Sessions>,
+        Write<'a, ActionBudget>,
+        Option<Write<'a, Recorder>>, // Only there when capturing
+        Write<'a, EventChannel<MapEvent>>,
+        Write<'a, ServerStats>,
+        Write<'a, Spectators>,
+        Write<'a, Metrics>,
     );
 
-    fn run(&mut self, (mut in_packs, mut lf, mut auth, mut net, sim_time, channel, mut pl): Self::SystemData) {
+    fn run(&mut self, (mut in_packs, mut lf, mut auth, mut net, sim_time, channel, mut pl, mut tcp, interest, mut latency, conf, mut sessions, mut budget, mut capture, mut map_events, mut stats, mut spectators, mut metrics): Self::SystemData) {
         let mut packs = Vec::<Pack>::new();
+        let mut kick = self.abusers.drain(..).collect::<Vec<SocketAddr>>(); // Thrown out, no coming back
+        let mut timed_out = Vec::<SocketAddr>::new(); // Can resume like any other drop
+        let now = Instant::now();
+        self.kicked.retain(|_, at| now.duration_since(*at).as_millis() < constants::KICK_COOLDOWN_MS);
         // First we get the Events
         for event in channel.read(&mut self.net_reader) {
             match event {
+                // Laminar keeps the connection open after a kick, so this is the only thing stopping them
+                NetworkSimulationEvent::Message(addr, _) | NetworkSimulationEvent::Connect(addr) if self.kicked.contains_key(addr) => {
+                    if let Some(tcp) = tcp.as_mut() {
+                        tcp.drop_stream(*addr);
+                    }
+                }
                 NetworkSimulationEvent::Message(addr, payload) => {
-                    info!("Package: {:?}", payload);
-                    let mut pk = Pack::from_bin(payload.to_vec());
-                    pk.dest = Dest::Ip(addr.clone());  // Update the client addr
-                    packs.push(pk);
+                    if !self.clients.contains(addr) {
+                        // Laminar won't say Connect again for a connection it never closed
+                        info!("Client connection back from {}", addr);
+                        self.clients.push(*addr);
+                    }
+                    self.last_heard.insert(*addr, now);
+                    stats.bytes_in += payload.len() as u64;
+                    for pack in self.partial.feed(*addr, payload) {
+                        let opened = pack
+                            .map_err(|e| format!("{:?}", e))
+                            .and_then(|pk| {
+                                if let (Cmd::Handshake(_), Some(capture)) = (&pk.cmd, capture.as_mut()) {
+                                    capture.record(*addr, Entry::Handshake);
+                                }
+                                self.unseal(*addr, pk, &conf, &mut net, &mut in_packs)
+                            });
+                        match opened {
+                            Ok(inner) => {
+                                for mut pk in inner {
+                                    pk.dest = Dest::Ip(addr.clone());  // Update the client addr
+                                    if let Some(capture) = capture.as_mut() {
+                                        // Signed proofs only answer one challenge, anything else might be a password
+                                        let recorded = match conf.auth {
+                                            AuthBackend::Keys => pk.clone(),
+                                            _ => capture::redact(pk.clone()),
+                                        };
+                                        capture.record(*addr, Entry::In(recorded));
+                                    }
+                                    stats.packs_in += 1;
+                                    metrics.incoming(&pk.cmd, bincode::serialized_size(&pk).unwrap_or(0));
+                                    packs.push(pk);
+                                }
+                            },
+                            Err(e) => {
+                                warn!("Malformed pack from {}: {}", addr, e);
+                                if self.strike(*addr) && !kick.contains(addr) {
+                                    warn!("Kicking {} after {} malformed packs", addr, constants::MAX_MALFORMED_PACKS);
+                                    kick.push(*addr);
+                                }
+                            },
+                        }
+                    }
                 }
                 NetworkSimulationEvent::Connect(addr) => {
                     info!("New client connection: {}", addr);
                     self.clients.push(*addr);
+                    self.last_heard.insert(*addr, now);
+                    if let Some(capture) = capture.as_mut() {
+                        capture.record(*addr, Entry::Connect);
+                    }
                 }
                 NetworkSimulationEvent::Disconnect(addr) => {
                     info!("Client Disconnected: {}", addr);
-                    self.clients.retain(|&x| x != *addr);
-                   
-                    match pl.get_from_ip(*addr) {
-                        Some(player) => {
-                            let id = player.id();
-                            lf.single_write(LifeformEvent::RemovePlayer(id));
-                            in_packs.single_write(Pack::new(Cmd::RemovePlayer(id), Dest::All)); 
-                        },
-                        None => warn!("Player disconnected that was not on the playerlist"),
+                    if let Some(capture) = capture.as_mut() {
+                        capture.record(*addr, Entry::Disconnect);
+                    }
+                    map_events.single_write(MapEvent::Disconnect(*addr));
+                    if let Some(uid) = self.drop_client(*addr, &mut pl, &mut latency, &mut spectators) {
+                        sessions.hold(uid, now);
                     }
                 }
                 NetworkSimulationEvent::RecvError(e) => {
@@ -109,19 +317,140 @@ impl<'a> System<'a> for TcpSystem {
             }
         }
         
+        // Gone quiet for too long, treat it like a disconnect
+        for (addr, heard) in self.last_heard.iter() {
+            if now.duration_since(*heard).as_millis() >= conf.idle_timeout_ms as u128 && !kick.contains(addr) {
+                warn!("Dropping {}, nothing heard for {}ms", addr, conf.idle_timeout_ms);
+                timed_out.push(*addr);
+            }
+        }
+
+        // Hang up on anyone that keeps sending us garbage or has timed out
+        for (addr, resumable) in kick.iter().map(|a| (*a, false)).chain(timed_out.iter().map(|a| (*a, true))) {
+            if let Some(tcp) = tcp.as_mut() {
+                tcp.drop_stream(addr);
+            }
+            if !resumable {
+                self.kicked.insert(addr, now);
+            }
+            packs.retain(|p| p.ip() != Some(addr));
+            map_events.single_write(MapEvent::Disconnect(addr));
+            match (self.drop_client(addr, &mut pl, &mut latency, &mut spectators), resumable) {
+                (Some(uid), true) => sessions.hold(uid, now),
+                (Some(uid), false) => {
+                    sessions.revoke(uid);
+                    budget.forget(uid);
+                    remove_player(uid, &mut lf, &mut in_packs);
+                },
+                (None, _) => (),
+            }
+        }
+
+        // Anyone that didn't make it back in time is gone for good
+        for uid in sessions.expired(now, conf.resume_grace_ms) {
+            info!("Lifeform {} did not come back, removing it", uid);
+            budget.forget(uid);
+            remove_player(uid, &mut lf, &mut in_packs);
+        }
+
+        // Check everyone is still there
+        if now.duration_since(self.ping_timer).as_millis() >= constants::PING_INTERVAL_MS {
+            self.ping_timer = now;
+            self.nonce += 1;
+            for addr in &self.clients {
+                self.pinged.insert(*addr, (self.nonce, now));
+                in_packs.single_write(Pack::new(Cmd::Ping(self.nonce), Dest::Ip(*addr)));
+            }
+        }
+
+        stats.clients = self.clients.len() as u32;
+        stats.lifeforms = pl.list.iter().filter(|l| l.is_some()).count() as u32;
+
         // Then we process the Events
         for pack in packs {
             match &pack.cmd {
-                Cmd::Connect(s) => auth.single_write(AuthEvent::Connect(s.to_string(), pack.ip().unwrap())),
+                Cmd::Ping(n) => in_packs.single_write(Pack::new(Cmd::Pong(*n), pack.dest.clone())),
+                Cmd::Pong(n) => {
+                    let addr = pack.ip().unwrap();
+                    if let Some((nonce, sent)) = self.pinged.get(&addr) {
+                        if nonce == n {
+                            latency.set(addr, now.duration_since(*sent));
+                            self.pinged.remove(&addr);
+                        }
+                    }
+                },
+                Cmd::Greet(version) => auth.single_write(AuthEvent::Greet(*version, pack.ip().unwrap())),
+                Cmd::Connect(hello) => {
+                    let addr = pack.ip().unwrap();
+                    let encrypted = self.secure.get(&addr).map_or(false, |c| c.is_ready());
+                    let tries = self.logins.entry(addr).or_insert(0);
+                    *tries += 1;
+                    if *tries > constants::MAX_LOGIN_ATTEMPTS {
+                        if !self.abusers.contains(&addr) {
+                            warn!("Kicking {} after {} tries at logging in", addr, constants::MAX_LOGIN_ATTEMPTS);
+                            self.abusers.push(addr);
+                        }
+                    }
+                    // A different version gets told that first, auth does it
+                    else if conf.auth == AuthBackend::Passwords && !encrypted && hello.version == PROTOCOL_VERSION {
+                        warn!("{} sent a password in the clear, turning it away", addr);
+                        let reason = "Passwords only go over an encrypted connection, turn on encrypt".to_string();
+                        in_packs.single_write(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(reason)), pack.dest.clone()));
+                    }
+                    else {
+                        auth.single_write(AuthEvent::Connect(hello.clone(), addr));
+                    }
+                },
                 Cmd::Action(act) => {
                     if let Some(player) = pl.get_from_ip(pack.ip().unwrap()) {
-                        lf.single_write(LifeformEvent::Action(act.clone(), player));
+                        if self.within_budget(pack.ip().unwrap(), &player, act, &mut budget, now) {
+                            lf.single_write(LifeformEvent::Action(act.clone(), player));
+                        }
+                    }
+                    else {
+                        warn!("Pack from someone not on the playerlist!");
+                    }
+                },
+                Cmd::Input(seq, act) => {
+                    if let Some(player) = pl.get_from_ip(pack.ip().unwrap()) {
+                        if self.within_budget(pack.ip().unwrap(), &player, act, &mut budget, now) {
+                            lf.single_write(LifeformEvent::Input(*seq, act.clone(), player));
+                        }
+                        else {
+                            // Still ack it so their prediction snaps back
+                            in_packs.single_write(Pack::new(Cmd::Ack(Ack::new(*seq, &player)), pack.dest.clone()));
+                        }
                     }
                     else {
                         warn!("Pack from someone not on the playerlist!");
                     }
                 },
                 Cmd::RemovePlayer(uid) => lf.single_write(LifeformEvent::RemovePlayer(*uid)),
+                Cmd::Watch(room) => auth.single_write(AuthEvent::Watch(room.clone(), pack.ip().unwrap())),
+                Cmd::Stats => {
+                    // Only for someone that's logged in, not anyone that can open a socket
+                    let addr = pack.ip().unwrap();
+                    if pl.get_from_ip(addr).is_some() || spectators.room_of(addr).is_some() {
+                        in_packs.single_write(Pack::new(Cmd::StatsReply(stats.clone()), pack.dest.clone()));
+                    }
+                    else {
+                        warn!("{} asked for stats without logging in", addr);
+                    }
+                },
+                Cmd::FetchFile(name) => map_events.single_write(MapEvent::Fetch(name.clone(), pack.ip().unwrap())),
+                Cmd::Resync(uid) => {
+                    // Only hand out lifeforms they're allowed to see
+                    let asker = pl.get_from_ip(pack.ip().unwrap()).map(|p| p.id());
+                    let watching = spectators.room_of(pack.ip().unwrap());
+                    let allowed = match (asker, watching) {
+                        (Some(asker), _) => asker == *uid || interest.sees(asker, *uid),
+                        (None, Some(room)) => pl.get_from_id(*uid).map_or(false, |lf| lf.room == *room),
+                        (None, None) => false,
+                    };
+                    if let (true, Some(lifeform)) = (allowed, pl.get_from_id(*uid)) {
+                        in_packs.single_write(Pack::new(Cmd::Resynced(lifeform), pack.dest.clone()));
+                    }
+                },
                 _ => (),
             }
         }
@@ -129,37 +458,321 @@ impl<'a> System<'a> for TcpSystem {
         // This is the new way!
         for _frame in sim_time.sim_frames_to_run() {
             for pack in in_packs.read(&mut self.event_reader) {
-                match &pack.dest {
-                    // Just send to one address 
-                    Dest::Ip(addr) => {
-                        // info!("Sending pack: {:?} to: {:?}", pack, addr);
-                        net.send(*addr, &pack.to_bin());
-                    },
-                    // Broadcast message
-                    Dest::All => {
-                        for addr in &self.clients {
-                            // info!("Sending pack: {:?} to: {:?}", pack, addr);
-                            net.send(*addr, &pack.to_bin());
-                        }
-                    },
-                    Dest::Room(name) => {
-                        // Get all the ip's in the room
-                        let ips = pl.ip_in_room(&name);
-                        for ip in ips { 
-                            // info!("Sending pack: {:?} to: {:?}", pack, ip);
-                            net.send(ip, &pack.to_bin());
-                        }
-                    },
-                    Dest::AllExcept(ip) => {
-                        for addr in &self.clients {
-                            if addr != ip {
-                                // info!("Sending pack: {:?} to: {:?}", pack, addr);
-                                net.send(*addr, &pack.to_bin());
-                            }
+                let bin = match pack.to_bin() {
+                    Ok(bin) => bin,
+                    Err(e) => {
+                        error!("Could not serialize pack {:?}: {:?}", pack, e);
+                        continue;
+                    }
+                };
+
+                for addr in recipients(&pack.dest, &self.clients, &mut pl, &interest, &spectators) {
+                    if let Some(capture) = capture.as_mut() {
+                        capture.record(addr, Entry::Out(pack.clone()));
+                    }
+                    self.batcher.push(addr, &pack, &bin);
+                    stats.packs_out += 1;
+                    metrics.outgoing(&pack.cmd, bin.len() as u64);
+
+                    // They get to hear why before we hang up
+                    if let Cmd::Kicked(_) = pack.cmd {
+                        if !self.abusers.contains(&addr) {
+                            self.abusers.push(addr);
                         }
                     }
                 }
             }
+
+            // One message per address, not one per pack
+            for (addr, batch) in self.batcher.drain() {
+                stats.bytes_out += batch.bin.len() as u64;
+                send(&mut net, addr, &batch, self.secure.get_mut(&addr));
+            }
+        }
+    }
+}
+
+/// Who a pack is going to
+fn recipients(
+    dest: &Dest,
+    clients: &[SocketAddr],
+    pl: &mut LifeformList,
+    interest: &Interest,
+    spectators: &Spectators,
+) -> Vec<SocketAddr> {
+    match dest {
+        // Just send to one address 
+        Dest::Ip(addr) => vec![*addr],
+        // Broadcast message
+        Dest::All => clients.to_vec(),
+        // Get all the ip's in the room
+        Dest::Room(name) => {
+            let mut ips = pl.ip_in_room(&name);
+            ips.extend(spectators.watching(name));
+            ips
+        },
+        Dest::AllExcept(ip) => clients.iter().filter(|addr| *addr != ip).cloned().collect(),
+        // Anyone that can see the lifeform, including itself, and anyone spectating its room
+        Dest::Seen(uid) => {
+            let mut ids = interest.watchers(*uid);
+            ids.push(*uid);
+            let mut ips: Vec<SocketAddr> = ids.into_iter()
+                .filter_map(|id| pl.get_from_id(id).and_then(|lf| lf.ip))
+                .collect();
+            if let Some(lifeform) = pl.get_from_id(*uid) {
+                ips.extend(spectators.watching(&lifeform.room));
+            }
+            ips
+        },
+    }
+}
+
+/// Tell everyone a lifeform is gone
+fn remove_player(uid: u64, lf: &mut EventChannel<LifeformEvent>, in_packs: &mut EventChannel<Pack>) {
+    lf.single_write(LifeformEvent::RemovePlayer(uid));
+    in_packs.single_write(Pack::new(Cmd::RemovePlayer(uid), Dest::All));
+}
+
+/// Send over whichever transport we're running, with the delivery the batch asks for.
+/// Sealed first if the client did a handshake.
+fn send(net: &mut TransportResource, addr: SocketAddr, batch: &Batch, channel: Option<&mut SecureChannel>) {
+    let sealed = match channel {
+        Some(channel) => channel.seal(&batch.bin, batch.delivery).and_then(|p| p.to_bin().map_err(|e| format!("{:?}", e))),
+        None => Ok(batch.bin.clone()),
+    };
+    match sealed {
+        Ok(bin) => net.send_with_requirements(addr, &bin, batch.delivery, UrgencyRequirement::OnTick),
+        Err(e) => error!("Could not seal a batch for {}: {}", addr, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::ConnectReply;
+    use amethyst::ecs::{RunNow, WorldExt};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// One sim frame's worth of time, then a run of the system
+    fn tick(world: &mut World, system: &mut TcpSystem) {
+        {
+            let mut time = world.write_resource::<NetworkSimulationTime>();
+            time.reset_frame_lag();
+            let frame = time.per_frame_duration();
+            time.update_elapsed(frame);
+            time.increment_frame_number();
         }
+        system.run_now(world);
+    }
+
+    fn hear(world: &mut World, event: NetworkSimulationEvent) {
+        world.write_resource::<EventChannel<NetworkSimulationEvent>>().single_write(event);
+    }
+
+    /// Who got sent a `RemovePlayer` for this uid since last time
+    fn told(world: &mut World, uid: u64) -> Vec<SocketAddr> {
+        world.write_resource::<TransportResource>()
+            .drain_messages(|_| true)
+            .into_iter()
+            .flat_map(|m| Pack::from_bin_many(m.payload.to_vec()).into_iter().map(move |p| (m.destination, p)))
+            .filter(|(_, p)| p.as_ref().map_or(false, |p| p.cmd == Cmd::RemovePlayer(uid)))
+            .map(|(addr, _)| addr)
+            .collect()
+    }
+
+    /// Two players that can see each other in town, one off in the cave and
+    /// someone spectating town
+    fn crowd() -> (Vec<SocketAddr>, LifeformList, Interest, Spectators) {
+        let mut pl = LifeformList::new();
+        for (uid, room) in vec![(1, "town"), (2, "town"), (3, "cave")] {
+            let mut player = LifeformComponent::new_player(format!("p{}", uid), addr(4000 + uid as u16), uid);
+            player.room = room.to_string();
+            pl.add(player);
+        }
+        let mut interest = Interest::new();
+        for uid in 1..=3 {
+            interest.refresh(&pl.get_from_id(uid).unwrap(), &pl);
+        }
+        let mut spectators = Spectators::new();
+        spectators.watch(addr(4009), "town".to_string());
+        let clients = vec![addr(4001), addr(4002), addr(4003), addr(4009)];
+        (clients, pl, interest, spectators)
+    }
+
+    #[test]
+    fn batching_changes_nothing_but_the_message_count() {
+        let (clients, mut pl, interest, spectators) = crowd();
+        let me = pl.get_from_id(1).unwrap();
+        let dests = vec![
+            Dest::Ip(addr(4001)),
+            Dest::All,
+            Dest::Room("town".to_string()),
+            Dest::AllExcept(addr(4001)),
+            Dest::Seen(1),
+        ];
+
+        for dest in dests {
+            // Mixed delivery, and more than fits in one batch
+            let mut packs = Vec::<Pack>::new();
+            for n in 0..40 {
+                packs.push(Pack::new(Cmd::ConnectReply(ConnectReply::Reject(format!("{:>60}", n))), dest.clone()));
+                if n % 7 == 0 {
+                    packs.push(Pack::new(Cmd::UpdatePlayer(me.clone()), dest.clone()));
+                }
+            }
+            let total: usize = packs.iter().map(|p| p.to_bin().unwrap().len()).sum();
+            assert!(total > constants::MAX_BATCH_BYTES);
+
+            let mut unbatched = HashMap::<SocketAddr, Vec<Cmd>>::new();
+            let mut batcher = Batcher::new();
+            for pack in packs.iter() {
+                let bin = pack.to_bin().unwrap();
+                for addr in recipients(&pack.dest, &clients, &mut pl, &interest, &spectators) {
+                    unbatched.entry(addr).or_insert_with(Vec::new).push(pack.cmd.clone());
+                    batcher.push(addr, pack, &bin);
+                }
+            }
+            assert!(!unbatched.is_empty(), "{:?} reached nobody", dest);
+
+            let mut batched = HashMap::<SocketAddr, Vec<Cmd>>::new();
+            let mut messages = 0;
+            for (addr, batch) in batcher.drain() {
+                assert!(batch.bin.len() <= constants::MAX_BATCH_BYTES);
+                let cmds: Vec<Cmd> = Pack::from_bin_many(batch.bin).into_iter().map(|p| p.unwrap().cmd).collect();
+                assert_eq!(cmds.len(), batch.count);
+                assert!(cmds.iter().all(|cmd| cmd.delivery() == batch.delivery));
+                batched.entry(addr).or_insert_with(Vec::new).extend(cmds);
+                messages += 1;
+            }
+            assert_eq!(batched, unbatched, "{:?}", dest);
+            assert!(messages > unbatched.len(), "{:?} never went over a batch", dest);
+        }
+    }
+
+    #[test]
+    fn garbage_gets_kicked_and_everyone_else_is_fine() {
+        let mut world = World::new();
+        world.insert(TcpNetworkResource::new(None));
+        let mut system = TcpSystemDesc::default().build(&mut world);
+        let mut lf_reader = world.fetch_mut::<EventChannel<LifeformEvent>>().register_reader();
+        let (bad, good) = (addr(4000), addr(4001));
+
+        hear(&mut world, NetworkSimulationEvent::Connect(bad));
+        hear(&mut world, NetworkSimulationEvent::Connect(good));
+        tick(&mut world, &mut system);
+        assert_eq!(system.clients, vec![bad, good]);
+
+        for n in 0..constants::MAX_MALFORMED_PACKS as u64 {
+            assert!(system.clients.contains(&bad), "kicked after only {} bad packs", n);
+            hear(&mut world, NetworkSimulationEvent::Message(bad, vec![0xff; 16].into()));
+            let pack = Pack::new(Cmd::RemovePlayer(n), Dest::All).to_bin().unwrap();
+            hear(&mut world, NetworkSimulationEvent::Message(good, pack.into()));
+            tick(&mut world, &mut system);
+
+            // The good one still gets through every time
+            let heard: Vec<u64> = world.read_resource::<EventChannel<LifeformEvent>>()
+                .read(&mut lf_reader)
+                .filter_map(|e| match e {
+                    LifeformEvent::RemovePlayer(uid) => Some(*uid),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(heard, vec![n]);
+        }
+        assert_eq!(system.clients, vec![good]);
+
+        // Only the one left hears about anything now
+        world.write_resource::<EventChannel<Pack>>().single_write(Pack::new(Cmd::RemovePlayer(99), Dest::All));
+        tick(&mut world, &mut system);
+        assert_eq!(told(&mut world, 99), vec![good]);
+    }
+
+    #[test]
+    fn split_packs_are_not_strikes() {
+        let mut world = World::new();
+        world.insert(TcpNetworkResource::new(None));
+        let mut system = TcpSystemDesc::default().build(&mut world);
+        let mut lf_reader = world.fetch_mut::<EventChannel<LifeformEvent>>().register_reader();
+        let client = addr(4000);
+
+        hear(&mut world, NetworkSimulationEvent::Connect(client));
+        tick(&mut world, &mut system);
+
+        // Every pack cut in two, far more times than it takes to get kicked
+        for n in 0..constants::MAX_MALFORMED_PACKS as u64 * 2 {
+            let pack = Pack::new(Cmd::RemovePlayer(n), Dest::All).to_bin().unwrap();
+            let (head, tail) = pack.split_at(pack.len() / 2);
+            hear(&mut world, NetworkSimulationEvent::Message(client, head.to_vec().into()));
+            tick(&mut world, &mut system);
+            hear(&mut world, NetworkSimulationEvent::Message(client, tail.to_vec().into()));
+            tick(&mut world, &mut system);
+
+            let heard: Vec<u64> = world.read_resource::<EventChannel<LifeformEvent>>()
+                .read(&mut lf_reader)
+                .filter_map(|e| match e {
+                    LifeformEvent::RemovePlayer(uid) => Some(*uid),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(heard, vec![n]);
+        }
+        assert_eq!(system.clients, vec![client]);
+        assert!(system.malformed.get(&client).is_none());
+    }
+
+    #[test]
+    fn kicked_laminar_clients_stay_kicked() {
+        // No TcpNetworkResource, so there's no stream to close on them
+        let mut world = World::new();
+        let mut system = TcpSystemDesc::default().build(&mut world);
+        let mut lf_reader = world.fetch_mut::<EventChannel<LifeformEvent>>().register_reader();
+        let bad = addr(4000);
+
+        hear(&mut world, NetworkSimulationEvent::Connect(bad));
+        for _ in 0..constants::MAX_MALFORMED_PACKS {
+            hear(&mut world, NetworkSimulationEvent::Message(bad, vec![0xff; 16].into()));
+        }
+        tick(&mut world, &mut system);
+        assert!(system.clients.is_empty());
+
+        // Still sending over the same connection, none of it gets through or starts a new count
+        for n in 0..constants::MAX_MALFORMED_PACKS as u64 * 2 {
+            let pack = Pack::new(Cmd::RemovePlayer(n), Dest::All).to_bin().unwrap();
+            hear(&mut world, NetworkSimulationEvent::Message(bad, pack.into()));
+            hear(&mut world, NetworkSimulationEvent::Connect(bad));
+            tick(&mut world, &mut system);
+        }
+        let heard = world.read_resource::<EventChannel<LifeformEvent>>().read(&mut lf_reader).count();
+        assert_eq!(heard, 0);
+        assert!(system.clients.is_empty());
+        assert!(system.malformed.get(&bad).is_none());
+    }
+
+    #[test]
+    fn stats_are_only_for_players() {
+        let mut world = World::new();
+        world.insert(TcpNetworkResource::new(None));
+        let mut system = TcpSystemDesc::default().build(&mut world);
+        let (stranger, player) = (addr(4000), addr(4001));
+        world.write_resource::<LifeformList>().add(LifeformComponent::new_player("p1".to_string(), player, 1));
+
+        hear(&mut world, NetworkSimulationEvent::Connect(stranger));
+        hear(&mut world, NetworkSimulationEvent::Connect(player));
+        for addr in vec![stranger, player] {
+            let pack = Pack::new(Cmd::Stats, Dest::All).to_bin().unwrap();
+            hear(&mut world, NetworkSimulationEvent::Message(addr, pack.into()));
+        }
+        tick(&mut world, &mut system);
+
+        let answered: Vec<SocketAddr> = world.write_resource::<TransportResource>()
+            .drain_messages(|_| true)
+            .into_iter()
+            .filter(|m| Pack::from_bin_many(m.payload.to_vec()).into_iter().any(|p| matches!(p, Ok(Pack { cmd: Cmd::StatsReply(_), .. }))))
+            .map(|m| m.destination)
+            .collect();
+        assert_eq!(answered, vec![player]);
     }
 }
@@ -0,0 +1,32 @@
+use amethyst::ecs::{System, Write};
+use std::time::Instant;
+
+use crate::resources::ServerStats;
+
+/// When the current frame's work started
+#[derive(Default)]
+pub struct TickTimer(Option<Instant>);
+
+/// Goes first in the frame, behind a barrier so nothing runs alongside it
+pub struct TickStartSystem;
+
+impl<'a> System<'a> for TickStartSystem {
+    type SystemData = Write<'a, TickTimer>;
+
+    fn run(&mut self, mut timer: Self::SystemData) {
+        timer.0 = Some(Instant::now());
+    }
+}
+
+/// Goes last in the frame, behind a barrier, and counts how long it all took
+pub struct TickEndSystem;
+
+impl<'a> System<'a> for TickEndSystem {
+    type SystemData = (Write<'a, TickTimer>, Write<'a, ServerStats>);
+
+    fn run(&mut self, (mut timer, mut stats): Self::SystemData) {
+        if let Some(started) = timer.0.take() {
+            stats.tick(started.elapsed());
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::components::LifeformComponent;
+use crate::constants;
+use crate::signing;
+
+/// Standing here sends a player to another realm server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Portal {
+    pub room: String,
+    pub x: f32,
+    pub y: f32,
+    pub realm: String, // Address of the other server
+    pub entry: String, // Entry point over there
+}
+
+impl Portal {
+    pub fn at(&self, lifeform: &LifeformComponent) -> bool {
+        lifeform.room == self.room && lifeform.x == self.x && lifeform.y == self.y
+    }
+}
+
+/// Named spot players from other realms arrive at
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EntryPoint {
+    pub name: String,
+    pub room: String,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// What the origin server vouches for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TicketBody {
+    pub id: u64, // Random, a ticket only works once
+    pub lifeform: LifeformComponent,
+    pub from: String,
+    pub to: String,
+    pub entry: String,
+    pub expires: u64, // Unix seconds
+}
+
+/// Lets a player carry their lifeform to another server. Signed by the
+/// origin server's `realm_key`, the destination checks it against its
+/// `trusted_realms`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransferTicket {
+    pub body: TicketBody,
+    pub signature: Vec<u8>,
+}
+
+impl TransferTicket {
+    pub fn issue(lifeform: LifeformComponent, from: &str, portal: &Portal, realm_key: &str) -> Result<Self, String> {
+        let body = TicketBody {
+            id: rand::random::<u64>(),
+            lifeform,
+            from: from.to_string(),
+            to: portal.realm.clone(),
+            entry: portal.entry.clone(),
+            expires: unix_now() + constants::TICKET_TTL_SECS,
+        };
+        let bin = bincode::serialize(&body).map_err(|e| format!("{:?}", e))?;
+        let signature = signing::sign(realm_key, &bin)?;
+        Ok(Self { body, signature })
+    }
+
+    /// Is this ticket for us, still good, and signed by a realm we trust
+    pub fn check(&self, me: &str, trusted: &[String]) -> Result<(), String> {
+        if self.body.to != me {
+            return Err(format!("Ticket is for {}, not here", self.body.to));
+        }
+        if self.body.expires < unix_now() {
+            return Err("Ticket has expired".to_string());
+        }
+        let bin = bincode::serialize(&self.body).map_err(|e| format!("{:?}", e))?;
+        if !trusted.iter().any(|key| signing::verify(key, &bin, &self.signature)) {
+            return Err(format!("Ticket from {} isn't signed by a realm we trust", self.body.from));
+        }
+        Ok(())
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    const HERE: &str = "127.0.0.1:3461";
+
+    fn portal() -> Portal {
+        Portal { room: "town".to_string(), x: 8.0, y: 24.0, realm: HERE.to_string(), entry: "gate".to_string() }
+    }
+
+    /// A ticket, the key it was signed with and the public half
+    fn ticket() -> (TransferTicket, String, String) {
+        let (secret, public) = signing::generate_key().unwrap();
+        let player = LifeformComponent::new_player("Turnip".to_string(), SocketAddr::from(([127, 0, 0, 1], 4000)), 1);
+        let ticket = TransferTicket::issue(player, "127.0.0.1:3460", &portal(), &secret).unwrap();
+        (ticket, secret, public)
+    }
+
+    fn resign(ticket: &mut TransferTicket, secret: &str) {
+        ticket.signature = signing::sign(secret, &bincode::serialize(&ticket.body).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn good_ticket() {
+        let (ticket, _, public) = ticket();
+        assert_eq!(ticket.check(HERE, &[public]), Ok(()));
+    }
+
+    #[test]
+    fn wrong_server() {
+        let (ticket, _, public) = ticket();
+        let err = ticket.check("127.0.0.1:9999", &[public]).unwrap_err();
+        assert!(err.contains("not here"), "{}", err);
+    }
+
+    #[test]
+    fn expired() {
+        let (mut ticket, secret, public) = ticket();
+        ticket.body.expires = unix_now() - 1;
+        resign(&mut ticket, &secret);
+        let err = ticket.check(HERE, &[public]).unwrap_err();
+        assert!(err.contains("expired"), "{}", err);
+    }
+
+    #[test]
+    fn untrusted_key() {
+        let (ticket, _, _) = ticket();
+        let (_, stranger) = signing::generate_key().unwrap();
+        let err = ticket.check(HERE, &[stranger]).unwrap_err();
+        assert!(err.contains("trust"), "{}", err);
+        assert!(ticket.check(HERE, &[]).is_err());
+    }
+
+    #[test]
+    fn tampered_body() {
+        let (ticket, _, public) = ticket();
+        let mut tampered = ticket.clone();
+        tampered.body.lifeform.hp = 9000.0;
+        assert!(tampered.check(HERE, &[public.clone()]).unwrap_err().contains("trust"));
+        let mut tampered = ticket.clone();
+        tampered.body.entry = "throne room".to_string();
+        assert!(tampered.check(HERE, &[public.clone()]).unwrap_err().contains("trust"));
+        let mut tampered = ticket;
+        tampered.signature[0] ^= 1;
+        assert!(tampered.check(HERE, &[public]).unwrap_err().contains("trust"));
+    }
+}